@@ -0,0 +1,33 @@
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{rule::AggregateOp, value::Value};
+
+use super::{builtin::BUILTINS, error::Error};
+
+fn call_builtin(name: &str, args: &[Value]) -> Result<Value, Error> {
+    BUILTINS.get(&Symbol::new(name)).unwrap().call(args)
+}
+
+/// Folds the items of one group into the result of the aggregate.
+/// Returns `None` when the aggregate has no value for no items, as min, max and avg.
+/// Min and max follow the order of values, so they apply to any items.
+pub fn aggregate(op: AggregateOp, items: &[Value]) -> Result<Option<Value>, Error> {
+    match op {
+        AggregateOp::Count => Ok(Some(Value::Uint(items.len() as u64))),
+        AggregateOp::Sum => call_builtin("+", items).map(Some),
+        AggregateOp::Min => Ok(items.iter().min().cloned()),
+        AggregateOp::Max => Ok(items.iter().max().cloned()),
+        AggregateOp::Avg if items.is_empty() => Ok(None),
+        AggregateOp::Avg => {
+            let sum = call_builtin("+", items)?;
+            // the average of decimals stays exact
+            let n = if sum.is_decimal() {
+                Value::Uint(items.len() as u64)
+            } else {
+                Value::Float(items.len() as f64)
+            };
+            call_builtin("/", &[sum, n]).map(Some)
+        }
+        AggregateOp::Collect => Ok(Some(Value::from(items))),
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{
+    fact::{BagRecord, ValueLine, ValueTable},
+    rule::{Expr, FactQuery, Goal, RuleBody, RuleRecord},
+    scope::Scope,
+    value::{Handle, Value},
+};
+
+use super::{
+    environment::Database,
+    error::Error,
+    query::{fresh_variable, query_rule_body, resolve},
+    stratify::{goal_dependencies, stratify, Key},
+};
+
+type Tuples = HashMap<Key, Vec<Vec<Value>>>;
+
+/// The relation holding the tuples new in the last round.
+fn delta_name(name: &Handle<Symbol>) -> Handle<Symbol> {
+    Handle::new(Symbol::new(&format!("{}#delta", name)))
+}
+
+/// The head tuples of the solutions of a rule body, solved against the facts of `db`.
+/// Solutions leaving a head variable unbound give no tuple.
+fn derive(db: &Handle<Database>, body: &RuleBody) -> Result<Vec<Vec<Value>>, Error> {
+    let head: Vec<Expr> = (0..body.prarms.len())
+        .map(|_| Expr::Variable(fresh_variable(&Handle::new(Symbol::new("_")))))
+        .collect();
+    let mut r = vec![];
+    for s in query_rule_body(body, None, db, &Scope::new(), &head) {
+        let s = s?;
+        let tuple: Option<Vec<_>> = head.iter().map(|x| resolve(x, &s).to_value()).collect();
+        r.extend(tuple);
+    }
+    Ok(r)
+}
+
+/// The variants of a body for a semi-naive round, one for each goal on
+/// a relation of the stratum, which reads the delta of it instead.
+/// A body using the stratum inside another goal is kept whole.
+fn delta_bodys(this: &RuleBody, stratum: &HashSet<Key>) -> Vec<RuleBody> {
+    let nested = this.bodys.iter().any(|goal| {
+        let mut record = vec![];
+        goal_dependencies(goal, false, &mut record);
+        !matches!(goal, Goal::Fact(_)) && record.iter().any(|(k, _)| stratum.contains(k))
+    });
+    if nested {
+        return vec![this.clone()];
+    }
+    this.bodys
+        .iter()
+        .enumerate()
+        .filter_map(|(i, goal)| match goal {
+            Goal::Fact(query) if stratum.contains(&(query.name.clone(), query.args.len())) => {
+                let mut bodys = this.bodys.to_vec();
+                bodys[i] = Goal::Fact(FactQuery {
+                    name: delta_name(&query.name),
+                    args: query.args.clone(),
+                });
+                Some(RuleBody {
+                    prarms: this.prarms.clone(),
+                    bodys: bodys.into(),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Keeps the tuples not seen in the rounds before, those of one round of all
+/// the rules of the relation. A bag keeps the duplicates within the round,
+/// a set only one of them; a recursion through a bag still ends.
+fn add_new(tuples: Vec<Vec<Value>>, key: &Key, bag: bool, seen: &mut HashSet<Vec<Value>>, record: &mut Tuples) {
+    let mut round = HashSet::new();
+    for x in tuples {
+        if !seen.contains(&x) && (round.insert(x.clone()) || bag) {
+            record.entry(key.clone()).or_default().push(x);
+        }
+    }
+    seen.extend(round);
+}
+
+/// Adds the delta to the relations and makes it the only delta.
+fn publish(db: &Database, stratum: &HashSet<Key>, delta: &Tuples) {
+    let mut facts = db.facts.write().unwrap();
+    for k in stratum {
+        facts.0.remove(&(delta_name(&k.0), k.1));
+    }
+    for (k, lines) in delta {
+        let mut table = ValueTable::new(k.1);
+        let full = facts.0.entry(k.clone()).or_insert_with(|| Handle::new(ValueTable::new(k.1)));
+        let full = Handle::make_mut(full);
+        for x in lines {
+            let line = ValueLine(x.as_slice().into());
+            full.push(line.clone());
+            table.push(line);
+        }
+        facts.0.insert((delta_name(&k.0), k.1), Handle::new(table));
+    }
+}
+
+/// Computes every relation defined by rules, one stratum after another,
+/// each to a fixpoint. After the first round of a stratum, a rule is only
+/// joined with the tuples new in the round before (semi-naive evaluation).
+/// Returns a database holding them and the facts, without rules.
+pub fn materialize(db: &Database) -> Result<Handle<Database>, Error> {
+    let rules = db.rules.read().unwrap().clone();
+    let bags = db.bags.read().unwrap().clone();
+    materialize_rules(db, &rules, &bags)
+}
+
+/// As `materialize`, with `rules` in place of the rules of the database
+/// and `bags` the relations of them keeping duplicates.
+pub fn materialize_rules(db: &Database, rules: &RuleRecord, bags: &BagRecord) -> Result<Handle<Database>, Error> {
+    let strata = stratify(rules)?;
+    // shares the tables of the facts, only the derived ones are new
+    let mut facts = db.facts.read().unwrap().clone();
+    // as in top-down evaluation, the rules of a relation shadow its facts
+    db.rules.read().unwrap().0.keys().chain(rules.0.keys()).for_each(|k| {
+        facts.0.remove(k);
+    });
+    let work = Handle::new(Database {
+        facts: RwLock::new(facts),
+        functions: RwLock::new(db.functions.read().unwrap().clone()),
+        foreigns: RwLock::new(db.foreigns.read().unwrap().clone()),
+        bags: RwLock::new(bags.clone()),
+        occurs_check: db.occurs_check,
+        hash_join: db.hash_join,
+        fixed_goal_order: db.fixed_goal_order,
+        ..Default::default()
+    });
+    let top = strata.values().copied().max().unwrap_or(0);
+    for s in 0..=top {
+        let stratum: HashSet<Key> = rules.0.keys().filter(|k| strata[*k] == s).cloned().collect();
+        let mut seen: HashMap<Key, HashSet<Vec<Value>>> = HashMap::new();
+        let mut delta = Tuples::new();
+        for k in stratum.iter() {
+            let mut tuples = vec![];
+            for body in rules.0[k].0.iter() {
+                tuples.extend(derive(&work, body)?);
+            }
+            add_new(tuples, k, bags.0.contains(k), seen.entry(k.clone()).or_default(), &mut delta);
+        }
+        while !delta.is_empty() {
+            publish(&work, &stratum, &delta);
+            let mut next = Tuples::new();
+            for k in stratum.iter() {
+                let mut tuples = vec![];
+                for body in rules.0[k].0.iter().flat_map(|x| delta_bodys(x, &stratum)) {
+                    tuples.extend(derive(&work, &body)?);
+                }
+                add_new(tuples, k, bags.0.contains(k), seen.get_mut(k).unwrap(), &mut next);
+            }
+            delta = next;
+        }
+        publish(&work, &stratum, &delta);
+    }
+    Ok(work)
+}
+
+/// The materialized relations of the database, computed on first use.
+pub fn materialized(db: &Database) -> Result<Handle<Database>, Error> {
+    if let Some(r) = db.materialized.read().unwrap().as_ref() {
+        return Ok(r.clone());
+    }
+    let r = materialize(db)?;
+    *db.materialized.write().unwrap() = Some(r.clone());
+    Ok(r)
+}
@@ -0,0 +1,637 @@
+use std::{cmp::Ordering, collections::HashMap, convert::TryFrom};
+
+use bigdecimal::BigDecimal;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Duration, NaiveDate, NaiveDateTime, Utc,
+};
+use lazy_static::lazy_static;
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{
+    function::Function,
+    value::{duration_from_nanos, duration_nanos, Dict, Handle, Pair, Tuple, Value},
+};
+
+use super::error::Error;
+
+#[derive(Debug, Clone)]
+enum Number {
+    Uint(u64),
+    Int(i64),
+    BigInt(BigInt),
+    Decimal(BigDecimal),
+    Float(f64),
+}
+
+impl Number {
+    fn from_value(v: &Value) -> Result<Number, Error> {
+        match v {
+            Value::Uint(x) => Ok(Number::Uint(*x)),
+            Value::Int(x) => Ok(Number::Int(*x)),
+            Value::BigInt(x) => Ok(Number::BigInt(BigInt::clone(x))),
+            Value::Decimal(x) => Ok(Number::Decimal(BigDecimal::clone(x))),
+            Value::Float(x) => Ok(Number::Float(*x)),
+            _ => Err(type_mismatch("number", v)),
+        }
+    }
+
+    /// The integer as a Uint or an Int when it fits in one of them, as `Value::from_bigint`.
+    fn from_bigint(x: BigInt) -> Number {
+        if let Some(x) = x.to_u64() {
+            Number::Uint(x)
+        } else if let Some(x) = x.to_i64() {
+            Number::Int(x)
+        } else {
+            Number::BigInt(x)
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match self {
+            Number::Uint(x) => *x as f64,
+            Number::Int(x) => *x as f64,
+            Number::BigInt(x) => x.to_f64().unwrap_or(f64::NAN),
+            Number::Decimal(x) => x.to_f64().unwrap_or(f64::NAN),
+            Number::Float(x) => *x,
+        }
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            Number::Uint(x) => i64::try_from(*x).ok(),
+            Number::Int(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    fn as_bigint(&self) -> BigInt {
+        match self {
+            Number::Uint(x) => BigInt::from(*x),
+            Number::Int(x) => BigInt::from(*x),
+            Number::BigInt(x) => x.clone(),
+            Number::Decimal(_) | Number::Float(_) => unreachable!(),
+        }
+    }
+
+    fn as_decimal(&self) -> BigDecimal {
+        match self {
+            Number::Decimal(x) => x.clone(),
+            Number::Float(_) => unreachable!(),
+            _ => BigDecimal::from(self.as_bigint()),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Number::Uint(x) => *x == 0,
+            Number::Int(x) => *x == 0,
+            Number::BigInt(x) => x.is_zero(),
+            Number::Decimal(x) => x.is_zero(),
+            Number::Float(x) => *x == 0.0,
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, Number::Float(_))
+    }
+}
+
+/// An arithmetic operator on each type of number,
+/// `None` when the result does not fit in the type.
+struct Arith {
+    uint: fn(u64, u64) -> Option<u64>,
+    int: fn(i64, i64) -> Option<i64>,
+    bigint: fn(BigInt, BigInt) -> BigInt,
+    decimal: fn(BigDecimal, BigDecimal) -> BigDecimal,
+    float: fn(f64, f64) -> f64,
+}
+
+const ADD: Arith = Arith {
+    uint: u64::checked_add,
+    int: i64::checked_add,
+    bigint: |a, b| a + b,
+    decimal: |a, b| a + b,
+    float: |a, b| a + b,
+};
+
+const SUB: Arith = Arith {
+    uint: u64::checked_sub,
+    int: i64::checked_sub,
+    bigint: |a, b| a - b,
+    decimal: |a, b| a - b,
+    float: |a, b| a - b,
+};
+
+const MUL: Arith = Arith {
+    uint: u64::checked_mul,
+    int: i64::checked_mul,
+    bigint: |a, b| a * b,
+    decimal: |a, b| a * b,
+    float: |a, b| a * b,
+};
+
+/// Integers divide with truncation, decimals to 100 significant digits.
+const DIV: Arith = Arith {
+    uint: u64::checked_div,
+    int: i64::checked_div,
+    bigint: |a, b| a / b,
+    decimal: |a, b| a / b,
+    float: |a, b| a / b,
+};
+
+const REM: Arith = Arith {
+    uint: u64::checked_rem,
+    int: i64::checked_rem,
+    bigint: |a, b| a % b,
+    decimal: |a, b| a % b,
+    float: |a, b| a % b,
+};
+
+/// Applies an arithmetic operator with promotion:
+/// Uint op Uint stays Uint unless the result does not fit, other integers
+/// are computed as Int, or as BigInt when the result does not fit in Int or Uint.
+/// Any Decimal makes a Decimal, and any Float makes a Float.
+fn arith(a: Number, b: Number, op: &Arith) -> Number {
+    match (&a, &b) {
+        (Number::Float(_), _) | (_, Number::Float(_)) => Number::Float((op.float)(a.as_f64(), b.as_f64())),
+        (Number::Decimal(_), _) | (_, Number::Decimal(_)) => {
+            Number::Decimal((op.decimal)(a.as_decimal(), b.as_decimal()))
+        }
+        (Number::Uint(x), Number::Uint(y)) if (op.uint)(*x, *y).is_some() => Number::Uint((op.uint)(*x, *y).unwrap()),
+        _ => match a.as_i64().zip(b.as_i64()).and_then(|(x, y)| (op.int)(x, y)) {
+            Some(r) => Number::Int(r),
+            None => Number::from_bigint((op.bigint)(a.as_bigint(), b.as_bigint())),
+        },
+    }
+}
+
+impl From<Number> for Value {
+    fn from(i: Number) -> Self {
+        match i {
+            Number::Uint(x) => Value::Uint(x),
+            Number::Int(x) => Value::Int(x),
+            Number::BigInt(x) => Value::BigInt(Handle::new(x)),
+            Number::Decimal(x) => Value::Decimal(Handle::new(x)),
+            Number::Float(x) => Value::Float(x),
+        }
+    }
+}
+
+fn type_mismatch(expected: &'static str, found: &Value) -> Error {
+    Error::TypeMismatch {
+        expected,
+        found: found.clone(),
+    }
+}
+
+fn numbers(args: &[Value]) -> Result<Vec<Number>, Error> {
+    args.iter().map(Number::from_value).collect()
+}
+
+fn fold_arith(args: &[Value], init: Number, op: &Arith) -> Result<Value, Error> {
+    let r = numbers(args)?.into_iter().fold(init, |r, x| arith(r, x, op));
+    Ok(Value::from(r))
+}
+
+fn is_time(v: &Value) -> bool {
+    matches!(v, Value::Timestamp(_) | Value::Duration(_))
+}
+
+/// Adds a duration to a timestamp or to another duration.
+fn add_time(a: Value, b: &Value) -> Result<Value, Error> {
+    match (&a, b) {
+        (Value::Timestamp(t), Value::Duration(d)) | (Value::Duration(d), Value::Timestamp(t)) => {
+            t.checked_add_signed(*d).map(Value::Timestamp).ok_or(Error::Overflow)
+        }
+        (Value::Duration(x), Value::Duration(y)) => x.checked_add(y).map(Value::Duration).ok_or(Error::Overflow),
+        (Value::Timestamp(_) | Value::Duration(_), _) => Err(type_mismatch("duration", b)),
+        _ => Err(type_mismatch("timestamp or duration", &a)),
+    }
+}
+
+/// Subtracts a duration from a timestamp or from another duration,
+/// or gives the duration between two timestamps.
+fn sub_time(a: Value, b: &Value) -> Result<Value, Error> {
+    match (&a, b) {
+        (Value::Timestamp(x), Value::Timestamp(y)) => Ok(Value::Duration(x.signed_duration_since(*y))),
+        (Value::Timestamp(t), Value::Duration(d)) => {
+            t.checked_sub_signed(*d).map(Value::Timestamp).ok_or(Error::Overflow)
+        }
+        (Value::Duration(x), Value::Duration(y)) => x.checked_sub(y).map(Value::Duration).ok_or(Error::Overflow),
+        (Value::Timestamp(_), _) => Err(type_mismatch("timestamp or duration", b)),
+        (Value::Duration(_), _) => Err(type_mismatch("duration", b)),
+        _ => Err(type_mismatch("timestamp or duration", &a)),
+    }
+}
+
+fn add(args: &[Value]) -> Result<Value, Error> {
+    if args.iter().any(is_time) {
+        return args[1..].iter().try_fold(args[0].clone(), add_time);
+    }
+    fold_arith(args, Number::Uint(0), &ADD)
+}
+
+fn mul(args: &[Value]) -> Result<Value, Error> {
+    fold_arith(args, Number::Uint(1), &MUL)
+}
+
+fn sub(args: &[Value]) -> Result<Value, Error> {
+    if args.iter().any(is_time) {
+        return match args {
+            [Value::Duration(d)] => Ok(Value::Duration(-*d)),
+            [x] => Err(type_mismatch("duration", x)),
+            _ => args[1..].iter().try_fold(args[0].clone(), sub_time),
+        };
+    }
+    let mut args = numbers(args)?.into_iter();
+    let r = match (args.next(), args.len()) {
+        (None, _) => Number::Uint(0),
+        (Some(x), 0) => arith(Number::Uint(0), x, &SUB),
+        (Some(x), _) => args.fold(x, |r, x| arith(r, x, &SUB)),
+    };
+    Ok(Value::from(r))
+}
+
+fn div(args: &[Value]) -> Result<Value, Error> {
+    let mut args = numbers(args)?.into_iter();
+    let first = args.next().ok_or(Error::ArityMismatch {
+        name: Handle::new(Symbol::new("/")),
+        expected: 1,
+        found: 0,
+    })?;
+    args.try_fold(first, |r, x| {
+        if x.is_zero() && !r.is_float() && !x.is_float() {
+            return Err(Error::DivisionByZero);
+        }
+        Ok(arith(r, x, &DIV))
+    })
+    .map(Value::from)
+}
+
+fn rem(args: &[Value]) -> Result<Value, Error> {
+    let a = Number::from_value(&args[0])?;
+    let b = Number::from_value(&args[1])?;
+    if b.is_zero() && !a.is_float() && !b.is_float() {
+        return Err(Error::DivisionByZero);
+    }
+    Ok(Value::from(arith(a, b, &REM)))
+}
+
+fn is_number(v: &Value) -> bool {
+    matches!(
+        v,
+        Value::Uint(_) | Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) | Value::Float(_)
+    )
+}
+
+/// Numbers compare by value across all their types, strings, chars and
+/// bytes compare lexicographically, timestamps and durations in time.
+fn compare(a: &Value, b: &Value) -> Result<Option<Ordering>, Error> {
+    match (a, b) {
+        (Value::Str(x), Value::Str(y)) => Ok(Some(x.cmp(y))),
+        (Value::Char(x), Value::Char(y)) => Ok(Some(x.cmp(y))),
+        (Value::Bytes(x), Value::Bytes(y)) => Ok(Some(x.cmp(y))),
+        (Value::Timestamp(x), Value::Timestamp(y)) => Ok(Some(x.cmp(y))),
+        (Value::Duration(x), Value::Duration(y)) => Ok(Some(x.cmp(y))),
+        _ if !is_number(a) => Err(type_mismatch("number", a)),
+        _ if !is_number(b) => Err(type_mismatch("number", b)),
+        _ => Ok(a.cmp_number(b)),
+    }
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    if is_number(a) && is_number(b) {
+        a.cmp_number(b) == Some(Ordering::Equal)
+    } else {
+        a == b
+    }
+}
+
+fn compare_chain(args: &[Value], f: fn(Ordering) -> bool) -> Result<Value, Error> {
+    for w in args.windows(2) {
+        if !matches!(compare(&w[0], &w[1])?, Some(o) if f(o)) {
+            return Ok(Value::Bool(false));
+        }
+    }
+    Ok(Value::Bool(true))
+}
+
+fn boolean(v: &Value) -> Result<bool, Error> {
+    if let Value::Bool(x) = v {
+        Ok(*x)
+    } else {
+        Err(type_mismatch("bool", v))
+    }
+}
+
+fn uint(v: &Value) -> Result<usize, Error> {
+    match v {
+        Value::Uint(x) => usize::try_from(*x).map_err(|_| Error::Overflow),
+        Value::Int(x) => usize::try_from(*x).map_err(|_| Error::IndexOutOfRange {
+            index: v.clone(),
+            length: 0,
+        }),
+        _ => Err(type_mismatch("integer", v)),
+    }
+}
+
+fn string(v: &Value) -> Result<&Handle<String>, Error> {
+    if let Value::Str(x) = v {
+        Ok(x)
+    } else {
+        Err(type_mismatch("string", v))
+    }
+}
+
+fn pair(v: &Value) -> Result<&Pair, Error> {
+    if let Value::Pair(x) = v {
+        Ok(x)
+    } else {
+        Err(type_mismatch("pair", v))
+    }
+}
+
+fn tuple(v: &Value) -> Result<&Tuple, Error> {
+    if let Value::Tuple(x) = v {
+        Ok(x)
+    } else {
+        Err(type_mismatch("vec", v))
+    }
+}
+
+fn dict(v: &Value) -> Result<&Dict, Error> {
+    if let Value::Dict(x) = v {
+        Ok(x)
+    } else {
+        Err(type_mismatch("dict", v))
+    }
+}
+
+/// The items of a proper list.
+fn list_items(v: &Value) -> Result<Vec<Value>, Error> {
+    let mut r = vec![];
+    let mut this = v;
+    loop {
+        match this {
+            Value::Nil => return Ok(r),
+            Value::Pair(p) => {
+                r.push(p.0.clone());
+                this = &p.1;
+            }
+            _ => return Err(type_mismatch("list", v)),
+        }
+    }
+}
+
+fn concat(args: &[Value]) -> Result<Value, Error> {
+    let mut r = String::new();
+    for x in args {
+        match x {
+            Value::Str(s) => r.push_str(s),
+            Value::Char(c) => r.push(*c),
+            _ => return Err(type_mismatch("string", x)),
+        }
+    }
+    Ok(Value::Str(Handle::new(r)))
+}
+
+fn to_char(args: &[Value]) -> Result<Value, Error> {
+    let s = string(&args[0])?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Value::Char(c)),
+        _ => Err(type_mismatch("string of one char", &args[0])),
+    }
+}
+
+/// Integers fitting in a Uint or an Int stay one, as `(bigint 5)` is 5.
+fn to_bigint(args: &[Value]) -> Result<Value, Error> {
+    match &args[0] {
+        Value::Str(s) => Value::from_literal("bigint", s).ok_or_else(|| type_mismatch("integer string", &args[0])),
+        Value::Uint(_) | Value::Int(_) | Value::BigInt(_) => Ok(args[0].clone()),
+        _ => Err(type_mismatch("integer", &args[0])),
+    }
+}
+
+/// Floats are converted by their shortest representation, as 0.1 to 0.1.
+fn to_decimal(args: &[Value]) -> Result<Value, Error> {
+    let r = match &args[0] {
+        Value::Str(s) => {
+            return Value::from_literal("decimal", s).ok_or_else(|| type_mismatch("decimal string", &args[0]))
+        }
+        Value::Float(x) => x
+            .to_string()
+            .parse()
+            .map_err(|_| type_mismatch("finite number", &args[0]))?,
+        v => v.to_decimal().ok_or_else(|| type_mismatch("number", v))?,
+    };
+    Ok(Value::Decimal(Handle::new(r)))
+}
+
+/// An integer of any type, as nanoseconds.
+fn nanos(v: &Value) -> Result<i128, Error> {
+    match v {
+        Value::Uint(x) => Ok(*x as i128),
+        Value::Int(x) => Ok(*x as i128),
+        Value::BigInt(x) => x.to_i128().ok_or(Error::Overflow),
+        _ => Err(type_mismatch("integer", v)),
+    }
+}
+
+fn nanos_value(x: i128) -> Value {
+    Value::from(Number::from_bigint(BigInt::from(x)))
+}
+
+fn timestamp(v: &Value) -> Result<&DateTime<Utc>, Error> {
+    if let Value::Timestamp(x) = v {
+        Ok(x)
+    } else {
+        Err(type_mismatch("timestamp", v))
+    }
+}
+
+fn duration(v: &Value) -> Result<&Duration, Error> {
+    if let Value::Duration(x) = v {
+        Ok(x)
+    } else {
+        Err(type_mismatch("duration", v))
+    }
+}
+
+fn bytes(v: &Value) -> Result<&Handle<Vec<u8>>, Error> {
+    if let Value::Bytes(x) = v {
+        Ok(x)
+    } else {
+        Err(type_mismatch("bytes", v))
+    }
+}
+
+/// From an RFC 3339 string, or from nanoseconds since the Unix epoch.
+fn to_timestamp(args: &[Value]) -> Result<Value, Error> {
+    match &args[0] {
+        Value::Str(s) => Value::from_literal("timestamp", s).ok_or_else(|| type_mismatch("RFC 3339 time", &args[0])),
+        Value::Timestamp(_) => Ok(args[0].clone()),
+        v => {
+            let x = nanos(v)?;
+            let secs = i64::try_from(x.div_euclid(1_000_000_000)).map_err(|_| Error::Overflow)?;
+            DateTime::from_timestamp(secs, x.rem_euclid(1_000_000_000) as u32)
+                .map(Value::Timestamp)
+                .ok_or(Error::Overflow)
+        }
+    }
+}
+
+/// From a string as `1h30m`, or from nanoseconds.
+fn to_duration(args: &[Value]) -> Result<Value, Error> {
+    match &args[0] {
+        Value::Str(s) => Value::from_literal("duration", s).ok_or_else(|| type_mismatch("duration string", &args[0])),
+        Value::Duration(_) => Ok(args[0].clone()),
+        v => duration_from_nanos(nanos(v)?).map(Value::Duration).ok_or(Error::Overflow),
+    }
+}
+
+fn to_bytes(args: &[Value]) -> Result<Value, Error> {
+    match &args[0] {
+        Value::Str(s) => Value::from_literal("bytes", s).ok_or_else(|| type_mismatch("hex string", &args[0])),
+        Value::Bytes(_) => Ok(args[0].clone()),
+        v => Err(type_mismatch("hex string", v)),
+    }
+}
+
+/// Reads a time in a strftime format, a time without an offset is in UTC.
+fn parse_time(args: &[Value]) -> Result<Value, Error> {
+    let (text, format) = (string(&args[0])?, string(&args[1])?);
+    DateTime::parse_from_str(text, format)
+        .map(|x| x.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(text, format).map(|x| x.and_utc()))
+        .or_else(|_| NaiveDate::parse_from_str(text, format).map(|x| x.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+        .map(Value::Timestamp)
+        .map_err(|_| type_mismatch("time in the format", &args[0]))
+}
+
+/// Writes a time in UTC in a strftime format.
+fn format_time(args: &[Value]) -> Result<Value, Error> {
+    let t = timestamp(&args[0])?;
+    let items: Vec<_> = StrftimeItems::new(string(&args[1])?).collect();
+    if items.contains(&Item::Error) {
+        return Err(type_mismatch("time format", &args[1]));
+    }
+    let r = t.format_with_items(items.into_iter()).to_string();
+    Ok(Value::Str(Handle::new(r)))
+}
+
+fn nth(args: &[Value]) -> Result<Value, Error> {
+    let items = list_items(&args[0])?;
+    let i = uint(&args[1])?;
+    items.get(i).cloned().ok_or(Error::IndexOutOfRange {
+        index: args[1].clone(),
+        length: items.len(),
+    })
+}
+
+fn vec_ref(args: &[Value]) -> Result<Value, Error> {
+    let t = tuple(&args[0])?;
+    let i = uint(&args[1])?;
+    t.0.get(i).cloned().ok_or(Error::IndexOutOfRange {
+        index: args[1].clone(),
+        length: t.0.len(),
+    })
+}
+
+fn dict_get(args: &[Value]) -> Result<Value, Error> {
+    let key = string(&args[1])?;
+    dict(&args[0])?
+        .0
+        .get(key)
+        .cloned()
+        .ok_or_else(|| Error::MissingKey(key.clone()))
+}
+
+fn dict_insert(args: &[Value]) -> Result<Value, Error> {
+    let mut r = dict(&args[0])?.clone();
+    r.0.insert(string(&args[1])?.clone(), args[2].clone());
+    Ok(Value::Dict(Handle::new(r)))
+}
+
+fn dict_keys(args: &[Value]) -> Result<Value, Error> {
+    let keys: Vec<_> = dict(&args[0])?.0.keys().cloned().map(Value::Str).collect();
+    Ok(Value::from(keys.as_slice()))
+}
+
+fn builtins() -> Vec<Function> {
+    vec![
+        // arithmetic
+        Function::new("+", None, add),
+        Function::new("-", None, sub),
+        Function::new("*", None, mul),
+        Function::new("/", None, div),
+        Function::new("mod", Some(2), rem),
+        Function::new("bigint", Some(1), to_bigint),
+        Function::new("decimal", Some(1), to_decimal),
+        // comparison
+        Function::new("=", Some(2), |args| Ok(Value::Bool(equal(&args[0], &args[1])))),
+        Function::new("!=", Some(2), |args| Ok(Value::Bool(!equal(&args[0], &args[1])))),
+        Function::new("<", None, |args| compare_chain(args, Ordering::is_lt)),
+        Function::new("<=", None, |args| compare_chain(args, Ordering::is_le)),
+        Function::new(">", None, |args| compare_chain(args, Ordering::is_gt)),
+        Function::new(">=", None, |args| compare_chain(args, Ordering::is_ge)),
+        // boolean logic
+        Function::new("and", None, |args| {
+            let r: Result<Vec<_>, _> = args.iter().map(boolean).collect();
+            Ok(Value::Bool(r?.into_iter().all(|x| x)))
+        }),
+        Function::new("or", None, |args| {
+            let r: Result<Vec<_>, _> = args.iter().map(boolean).collect();
+            Ok(Value::Bool(r?.into_iter().any(|x| x)))
+        }),
+        Function::new("not", Some(1), |args| Ok(Value::Bool(!boolean(&args[0])?))),
+        // strings
+        Function::new("concat", None, concat),
+        Function::new("string-length", Some(1), |args| {
+            Ok(Value::Uint(string(&args[0])?.chars().count() as u64))
+        }),
+        Function::new("char", Some(1), to_char),
+        // lists
+        Function::new("cons", Some(2), |args| {
+            Ok(Value::Pair(Handle::new(Pair(args[0].clone(), args[1].clone()))))
+        }),
+        Function::new("car", Some(1), |args| Ok(pair(&args[0])?.0.clone())),
+        Function::new("cdr", Some(1), |args| Ok(pair(&args[0])?.1.clone())),
+        Function::new("list", None, |args| Ok(Value::from(args))),
+        Function::new("length", Some(1), |args| Ok(Value::Uint(list_items(&args[0])?.len() as u64))),
+        Function::new("nth", Some(2), nth),
+        // tuples
+        Function::new("vec", None, |args| Ok(Value::Tuple(Handle::new(Tuple(args.to_vec()))))),
+        Function::new("vec-ref", Some(2), vec_ref),
+        Function::new("vec-length", Some(1), |args| Ok(Value::Uint(tuple(&args[0])?.0.len() as u64))),
+        // bytes
+        Function::new("bytes", Some(1), to_bytes),
+        Function::new("bytes-length", Some(1), |args| Ok(Value::Uint(bytes(&args[0])?.len() as u64))),
+        Function::new("bytes-hex", Some(1), |args| {
+            let r: String = bytes(&args[0])?.iter().map(|x| format!("{:02x}", x)).collect();
+            Ok(Value::Str(Handle::new(r)))
+        }),
+        // time
+        Function::new("timestamp", Some(1), to_timestamp),
+        Function::new("duration", Some(1), to_duration),
+        Function::new("parse-time", Some(2), parse_time),
+        Function::new("format-time", Some(2), format_time),
+        Function::new("time-nanos", Some(1), |args| {
+            let t = timestamp(&args[0])?;
+            Ok(nanos_value(t.timestamp() as i128 * 1_000_000_000 + t.timestamp_subsec_nanos() as i128))
+        }),
+        Function::new("duration-nanos", Some(1), |args| Ok(nanos_value(duration_nanos(duration(&args[0])?)))),
+        // dicts
+        Function::new("dict-get", Some(2), dict_get),
+        Function::new("dict-insert", Some(3), dict_insert),
+        Function::new("dict-keys", Some(1), dict_keys),
+    ]
+}
+
+lazy_static! {
+    pub static ref BUILTINS: HashMap<Handle<Symbol>, Function> =
+        builtins().into_iter().map(|x| (x.name.clone(), x)).collect();
+}
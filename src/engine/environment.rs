@@ -1,11 +1,172 @@
-use std::sync::RwLock;
+use std::{collections::HashMap, sync::RwLock};
 
-use crate::structs::{fact::FactRecord, rule::RuleRecord};
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{
+    fact::{BagRecord, FactRecord, IndexRecord},
+    foreign::{ForeignRecord, ForeignRelation, ForeignTuples},
+    function::{Function, FunctionRecord},
+    plan::PlanRecord,
+    rule::{Expr, FactQuery, Goal, RuleRecord},
+    scope::Scope,
+    table::{TableRecord, TabledRecord},
+    value::{Handle, Value},
+};
+
+use super::{
+    error::Error,
+    query::{rename, unify},
+};
+
+/// How the queries of a database are solved.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Evaluation {
+    /// Resolution from the query goals down to the facts.
+    #[default]
+    TopDown,
+    /// Computes all the relations defined by rules first, then queries them as facts.
+    BottomUp,
+    /// Bottom-up, computing only the tuples relevant to the query.
+    /// The rules are rewritten with magic sets for each query.
+    Magic,
+}
 
 #[derive(Debug, Default)]
 pub struct Database {
     pub facts: RwLock<FactRecord>,
     pub rules: RwLock<RuleRecord>,
+    pub functions: RwLock<FunctionRecord>,
+    pub foreigns: RwLock<ForeignRecord>,
+    /// Check that a variable does not occur in the term it is bound to.
+    pub occurs_check: bool,
+    pub evaluation: Evaluation,
+    /// Solve the goals of bodies in the written order, instead of planning it.
+    pub fixed_goal_order: bool,
+    /// Solve runs of goals on facts set at a time, with hash joins.
+    pub hash_join: bool,
+    pub tabled: RwLock<TabledRecord>,
+    pub bags: RwLock<BagRecord>,
+    pub indexes: RwLock<IndexRecord>,
+    /// The relations computed by bottom-up evaluation, as a database of facts.
+    pub materialized: RwLock<Option<Handle<Database>>>,
+    /// The answers of tabled calls, shared by the queries until the database changes.
+    pub tables: RwLock<TableRecord>,
+    /// The orders of rule bodys found by the planner.
+    pub plans: RwLock<PlanRecord>,
+}
+
+impl Database {
+    /// Registers a native function, callable from rules, facts and defines.
+    /// It shadows the built-in function of the same name.
+    pub fn register_function<F>(&self, name: &str, arity: Option<usize>, body: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        let f = Function::new(name, arity, body);
+        self.functions.write().unwrap().0.insert(f.name.clone(), f);
+        self.clear_derived();
+    }
+
+    /// Registers a relation whose tuples come from a native callback.
+    /// It is queried as facts are, rules of the same name and arity take precedence.
+    pub fn register_relation<F>(&self, name: &str, arity: usize, body: F)
+    where
+        F: Fn(&[Option<Value>]) -> ForeignTuples + Send + Sync + 'static,
+    {
+        let r = ForeignRelation::new(name, arity, body);
+        let key = (r.name.clone(), arity);
+        self.foreigns.write().unwrap().0.insert(key, r);
+        self.clear_derived();
+    }
+
+    /// Adds an index on the columns of the facts of a relation,
+    /// kept up to date as facts are added. A relation without facts yet
+    /// gets it with its first fact. Relations given by rules or by a
+    /// native callback instead of facts can't be indexed.
+    pub fn add_index(&self, name: &str, arity: usize, cols: &[usize]) -> Result<(), Error> {
+        if let Some(i) = cols.iter().find(|i| **i >= arity) {
+            return Err(Error::IndexOutOfRange {
+                index: Value::Uint(*i as u64),
+                length: arity,
+            });
+        }
+        let key = (Handle::new(Symbol::new(name)), arity);
+        let unindexable = || Error::Unindexable {
+            name: key.0.clone(),
+            arity,
+        };
+        if self.rules.read().unwrap().0.contains_key(&key) {
+            return Err(unindexable());
+        }
+        let mut facts = self.facts.write().unwrap();
+        match facts.0.get_mut(&key) {
+            Some(table) => Handle::make_mut(table).add_index(cols),
+            None if self.foreigns.read().unwrap().0.contains_key(&key) => return Err(unindexable()),
+            None => self.indexes.write().unwrap().0.entry(key.clone()).or_default().push(cols.into()),
+        }
+        Ok(())
+    }
+
+    /// Keeps the duplicate facts of the relation and the duplicate solutions
+    /// of its rules, from now on. Relations are sets otherwise.
+    pub fn set_bag(&self, name: &str, arity: usize) {
+        let key = (Handle::new(Symbol::new(name)), arity);
+        self.bags.write().unwrap().0.insert(key);
+        self.clear_derived();
+    }
+
+    /// Removes the facts of the relation matching the args, where variables
+    /// match any value, the same value for each use of a variable but `_`.
+    /// Returns the number of facts removed.
+    pub fn retract(&self, name: &str, args: &[Expr]) -> usize {
+        let key = (Handle::new(Symbol::new(name)), args.len());
+        let goals = rename(&[Goal::Fact(FactQuery {
+            name: key.0.clone(),
+            args: args.into(),
+        })], &mut HashMap::new());
+        let args = match &goals[0] {
+            Goal::Fact(x) => x.args.clone(),
+            _ => unreachable!(),
+        };
+        let mut record = self.facts.write().unwrap();
+        let table = match record.0.get_mut(&key) {
+            Some(x) => Handle::make_mut(x),
+            None => return 0,
+        };
+        let bound: Vec<_> = args.iter().map(Expr::to_value).collect();
+        let ids: Vec<usize> = table
+            .lookup(&bound)
+            .unwrap_or_else(|| table.iter().map(|(i, _)| i).collect())
+            .into_iter()
+            .filter(|i| {
+                let scope = Scope::new();
+                let values = table.get(*i).unwrap().0.iter().cloned().map(Expr::Value);
+                args.iter()
+                    .zip(values)
+                    .try_for_each(|(x, v)| unify(x, &v, &scope, false))
+                    .is_ok()
+            })
+            .collect();
+        table.remove(&ids);
+        drop(record);
+        if !ids.is_empty() {
+            self.clear_derived();
+        }
+        ids.len()
+    }
+
+    /// Drops what was computed from the facts and rules, once they change.
+    pub fn clear_derived(&self) {
+        *self.materialized.write().unwrap() = None;
+        let mut tables = self.tables.write().unwrap();
+        tables.tables.clear();
+        tables.generation += 1;
+        drop(tables);
+        let mut plans = self.plans.write().unwrap();
+        plans.orders.clear();
+        plans.sensitive = None;
+        plans.generation += 1;
+    }
 }
 
 // pub type Env = (Handle<Database>, Handle<Scope>);
@@ -0,0 +1,73 @@
+use std::fmt::Display;
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::value::{Handle, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UnboundVariable(Handle<Symbol>),
+    UndefinedFunction(Handle<Symbol>),
+    ArityMismatch {
+        name: Handle<Symbol>,
+        expected: usize,
+        found: usize,
+    },
+    TypeMismatch {
+        expected: &'static str,
+        found: Value,
+    },
+    IndexOutOfRange {
+        index: Value,
+        length: usize,
+    },
+    /// A dict has no value for the key.
+    MissingKey(Handle<String>),
+    DivisionByZero,
+    Overflow,
+    /// Raised by native functions registered by the user.
+    Custom(String),
+    /// A relation depends on its own negation or aggregate through recursion.
+    Unstratifiable {
+        name: Handle<Symbol>,
+        arity: usize,
+    },
+    /// An index on a relation not given by facts.
+    Unindexable {
+        name: Handle<Symbol>,
+        arity: usize,
+    },
+    /// The input is neither a definition nor a query.
+    InvalidForm,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnboundVariable(k) => write!(f, "unbound variable: {}", k),
+            Error::UndefinedFunction(k) => write!(f, "undefined function: {}", k),
+            Error::ArityMismatch { name, expected, found } => write!(
+                f,
+                "function {} expects {} arguments, found {}",
+                name, expected, found
+            ),
+            Error::TypeMismatch { expected, found } => write!(f, "expected {}, found {}", expected, found),
+            Error::IndexOutOfRange { index, length } => {
+                write!(f, "index {} out of range for length {}", index, length)
+            }
+            Error::MissingKey(k) => write!(f, "no key \"{}\" in dict", k),
+            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::Overflow => write!(f, "arithmetic overflow"),
+            Error::Custom(message) => write!(f, "{}", message),
+            Error::Unstratifiable { name, arity } => write!(
+                f,
+                "rule {}/{} depends on its own negation or aggregate, the program is not stratifiable",
+                name, arity
+            ),
+            Error::Unindexable { name, arity } => {
+                write!(f, "relation {}/{} is not given by facts, it can't be indexed", name, arity)
+            }
+            Error::InvalidForm => write!(f, "invalid form"),
+        }
+    }
+}
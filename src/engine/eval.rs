@@ -1,19 +1,82 @@
+use std::collections::BTreeMap;
+
 use sexpr_ir::gast::{symbol::Symbol, Handle};
 
-use crate::structs::{rule::Expr, scope::Scope, value::Value};
+use crate::structs::{
+    rule::Expr,
+    scope::Scope,
+    value::{Dict, Pair, Tuple, Value},
+};
+
+use super::{builtin::BUILTINS, environment::Database, error::Error};
 
-fn eval_function(_name: &Handle<Symbol>, _args: &[Value], _env: &Handle<Scope>) -> Value {
-    todo!()
+fn eval_function(name: &Handle<Symbol>, args: &[Value], db: &Database) -> Result<Value, Error> {
+    let f = db.functions.read().unwrap().0.get(name).cloned();
+    if let Some(f) = f.as_ref().or_else(|| BUILTINS.get(name)) {
+        f.call(args)
+    } else {
+        Err(Error::UndefinedFunction(name.clone()))
+    }
 }
 
-pub fn eval_value(i: &Expr, env: &Handle<Scope>) -> Option<Value> {
+pub fn eval_value(i: &Expr, db: &Database, env: &Handle<Scope>) -> Result<Value, Error> {
     match i {
-        Expr::Value(v) => Some(v.clone()),
-        Expr::Variable(k) => env.find(k),
+        Expr::Value(v) => Ok(v.clone()),
+        Expr::Variable(k) => env.find(k).ok_or_else(|| Error::UnboundVariable(k.clone())),
         Expr::FunctionCall(c) => {
-            let r: Option<Vec<Value>> = c.args.iter().map(|x| eval_value(x, env)).collect();
+            let r: Result<Vec<Value>, Error> = c.args.iter().map(|x| eval_value(x, db, env)).collect();
             let r = r?;
-            Some(eval_function(&c.call_name, &r, env))
+            eval_function(&c.call_name, &r, db)
+        }
+        Expr::Pair(p) => {
+            let (left, right) = p.as_ref();
+            let r = Pair(eval_value(left, db, env)?, eval_value(right, db, env)?);
+            Ok(Value::Pair(Handle::new(r)))
+        }
+        Expr::Tuple(t) => {
+            let r: Result<Vec<Value>, Error> = t.iter().map(|x| eval_value(x, db, env)).collect();
+            Ok(Value::Tuple(Handle::new(Tuple(r?))))
+        }
+        Expr::Dict(entries, rest) => {
+            let mut r = match rest.as_ref().map(|x| eval_value(x, db, env)).transpose()? {
+                Some(Value::Dict(d)) => d.0.clone(),
+                Some(x) => {
+                    return Err(Error::TypeMismatch {
+                        expected: "dict",
+                        found: x,
+                    })
+                }
+                None => BTreeMap::new(),
+            };
+            for (k, v) in entries.iter() {
+                r.insert(k.clone(), eval_value(v, db, env)?);
+            }
+            Ok(Value::Dict(Handle::new(Dict(r))))
+        }
+    }
+}
+
+/// Evaluates the function calls inside a term,
+/// the rest of the term is kept as it is.
+pub fn eval_calls(i: &Expr, db: &Database, env: &Handle<Scope>) -> Result<Expr, Error> {
+    match i {
+        Expr::FunctionCall(_) => eval_value(i, db, env).map(Expr::Value),
+        Expr::Pair(p) => {
+            let (left, right) = p.as_ref();
+            Ok(Expr::Pair(Handle::new((eval_calls(left, db, env)?, eval_calls(right, db, env)?))))
+        }
+        Expr::Tuple(t) => {
+            let r: Result<Handle<[Expr]>, Error> = t.iter().map(|x| eval_calls(x, db, env)).collect();
+            Ok(Expr::Tuple(r?))
+        }
+        Expr::Dict(entries, rest) => {
+            let r: Result<Handle<[_]>, Error> = entries
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), eval_calls(v, db, env)?)))
+                .collect();
+            let rest = rest.as_ref().map(|x| eval_calls(x, db, env)).transpose()?;
+            Ok(Expr::Dict(r?, rest.map(Handle::new)))
         }
+        Expr::Value(_) | Expr::Variable(_) => Ok(i.clone()),
     }
 }
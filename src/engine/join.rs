@@ -0,0 +1,153 @@
+use std::{collections::HashMap, iter::once};
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{
+    rule::{Expr, FactQuery, Goal},
+    scope::SimpleScope,
+    value::{Handle, Value},
+};
+
+use super::{
+    environment::Database,
+    error::Error,
+    planner::has_call,
+    query::{and_then, is_builtin_goal, query_fact, query_goal, resolve, Bindings, Solutions},
+};
+
+/// Rows of values for some variables.
+struct Relation {
+    vars: Vec<Handle<Symbol>>,
+    rows: Vec<Vec<Value>>,
+}
+
+fn expr_variables(this: &Expr, record: &mut Vec<Handle<Symbol>>) {
+    match this {
+        Expr::Value(_) => {}
+        Expr::Variable(k) => {
+            if !record.contains(k) {
+                record.push(k.clone());
+            }
+        }
+        Expr::FunctionCall(c) => c.args.iter().for_each(|x| expr_variables(x, record)),
+        Expr::Pair(p) => {
+            expr_variables(&p.0, record);
+            expr_variables(&p.1, record);
+        }
+        Expr::Tuple(t) => t.iter().for_each(|x| expr_variables(x, record)),
+        Expr::Dict(entries, rest) => {
+            entries.iter().for_each(|(_, x)| expr_variables(x, record));
+            rest.iter().for_each(|x| expr_variables(x, record));
+        }
+    }
+}
+
+/// Goals on relations given by facts only, whose solutions bind all their variables.
+/// Args with a call, even inside a list, can't be solved before their variables are bound.
+fn is_joinable(this: &Goal, db: &Database) -> bool {
+    match this {
+        Goal::Fact(query) => {
+            let k = (query.name.clone(), query.args.len());
+            !is_builtin_goal(query)
+                && !query.args.iter().any(has_call)
+                && !db.rules.read().unwrap().0.contains_key(&k)
+                && !db.tabled.read().unwrap().0.contains(&k)
+                && db.facts.read().unwrap().0.contains_key(&k)
+        }
+        _ => false,
+    }
+}
+
+/// All the solutions of the goal, as values of its unbound variables.
+fn relation(this: &FactQuery, env: &Handle<Database>, scope: &Bindings) -> Result<Relation, Error> {
+    let mut vars = vec![];
+    this.args
+        .iter()
+        .for_each(|x| expr_variables(&resolve(x, scope), &mut vars));
+    let mut rows = vec![];
+    for s in query_fact(this, env, scope) {
+        let s = s?;
+        let row: Option<Vec<_>> = vars
+            .iter()
+            .map(|k| resolve(&Expr::Variable(k.clone()), &s).to_value())
+            .collect();
+        rows.extend(row);
+    }
+    Ok(Relation { vars, rows })
+}
+
+/// Joins the rows on their shared variables, by a hash table of the right rows.
+/// The rows come in the order of nested loops over left and then right.
+fn hash_join(left: Relation, right: Relation) -> Relation {
+    let shared: Vec<(usize, usize)> = left
+        .vars
+        .iter()
+        .enumerate()
+        .filter_map(|(i, k)| right.vars.iter().position(|x| x == k).map(|j| (i, j)))
+        .collect();
+    let rest: Vec<usize> = (0..right.vars.len())
+        .filter(|j| shared.iter().all(|(_, x)| x != j))
+        .collect();
+    let mut table: HashMap<Vec<Value>, Vec<usize>> = HashMap::new();
+    for (n, row) in right.rows.iter().enumerate() {
+        let key: Vec<_> = shared.iter().map(|(_, j)| row[*j].clone()).collect();
+        table.entry(key).or_default().push(n);
+    }
+    let mut rows = vec![];
+    for row in left.rows.iter() {
+        let key: Vec<_> = shared.iter().map(|(i, _)| row[*i].clone()).collect();
+        for n in table.get(&key).into_iter().flatten() {
+            let mut r = row.clone();
+            r.extend(rest.iter().map(|j| right.rows[*n][*j].clone()));
+            rows.push(r);
+        }
+    }
+    let mut vars = left.vars;
+    vars.extend(rest.iter().map(|j| right.vars[*j].clone()));
+    Relation { vars, rows }
+}
+
+fn join_run(this: &[FactQuery], env: &Handle<Database>, scope: &Bindings) -> Solutions {
+    let r: Result<Vec<_>, Error> = this.iter().map(|x| relation(x, env, scope)).collect();
+    let r = match r {
+        Ok(r) => r.into_iter().reduce(hash_join).unwrap(),
+        Err(e) => return Box::new(once(Err(e))),
+    };
+    let scope = scope.clone();
+    let vars = r.vars;
+    Box::new(r.rows.into_iter().map(move |row| {
+        let new_scope = scope.new_level(SimpleScope::new());
+        vars.iter()
+            .zip(row)
+            .for_each(|(k, v)| new_scope.set(k, &Expr::Value(v)));
+        Ok(new_scope)
+    }))
+}
+
+/// Solves the goals as `query_goals` does, but runs of goals on facts
+/// are solved set at a time: each to all of its solutions, then hash
+/// joined on their shared variables. The solutions are the same, in the same order.
+pub fn join_goals(this: &[Goal], env: &Handle<Database>, scope: &Bindings) -> Solutions {
+    let mut r: Solutions = Box::new(once(Ok(scope.clone())));
+    let mut i = 0;
+    while i < this.len() {
+        let run: Vec<FactQuery> = this[i..]
+            .iter()
+            .take_while(|x| is_joinable(x, env))
+            .filter_map(|x| match x {
+                Goal::Fact(query) => Some(query.clone()),
+                _ => None,
+            })
+            .collect();
+        let env = env.clone();
+        if run.len() >= 2 {
+            i += run.len();
+            r = and_then(r, move |scope| join_run(&run, &env, &scope));
+        } else {
+            let goal = this[i].clone();
+            i += 1;
+            r = and_then(r, move |scope| query_goal(&goal, &env, &scope));
+        }
+    }
+    r
+}
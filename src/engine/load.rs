@@ -1,28 +1,192 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::collections::{HashMap, HashSet};
 
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use sexpr_ir::gast::{symbol::Symbol, GAst, Handle};
 use sexpr_process::capture::{Capture, Catch};
 
 use crate::{
-    engine::query::query_fact,
+    engine::query::{query_goals, rename, resolve},
     structs::{
-        fact::{FactRecord, ValueLine, ValueTable},
-        rule::{Expr, FactQuery, Pattern, RuleBody, RuleRecord, RuleTable},
-        scope::{Scope, SimpleScope},
+        fact::{BagRecord, FactRecord, ValueLine, ValueTable},
+        rule::{Aggregate, Call, Expr, FactQuery, Goal, Pattern, RuleBody, RuleRecord},
+        scope::Scope,
+        value::Value,
     },
 };
 
-use super::{environment::Database, parser::FromGast, utils::*};
+use super::{
+    bottomup::{materialize_rules, materialized},
+    environment::{Database, Evaluation},
+    error::Error,
+    magic::magic_program,
+    parser::FromGast,
+    planner::plan,
+    stratify::{check_stratified, goal_dependencies, Key},
+    utils::*,
+};
+
+use super::eval::{eval_calls, eval_value};
+
+pub(crate) fn pattern_variables(this: &Pattern, record: &mut HashSet<Handle<Symbol>>) {
+    match this {
+        Pattern::Ignore | Pattern::Constant(_) => {}
+        Pattern::Variable(k) => {
+            record.insert(k.clone());
+        }
+        Pattern::Tuple(patterns) => patterns.iter().for_each(|x| pattern_variables(x, record)),
+        Pattern::List(patterns, extend) => {
+            patterns.iter().for_each(|x| pattern_variables(x, record));
+            if let Some(extend) = extend {
+                pattern_variables(extend, record);
+            }
+        }
+        Pattern::Dict(patterns, rest) => {
+            patterns.iter().for_each(|(_, x)| pattern_variables(x, record));
+            if let Some(rest) = rest {
+                pattern_variables(rest, record);
+            }
+        }
+    }
+}
+
+fn bind_defines_expr(this: &Expr, env: &Handle<Scope>, locals: &HashSet<Handle<Symbol>>) -> Expr {
+    match this {
+        Expr::Variable(k) if !locals.contains(k) => env.find(k).map_or_else(|| this.clone(), Expr::Value),
+        Expr::Value(_) | Expr::Variable(_) => this.clone(),
+        Expr::FunctionCall(c) => Expr::FunctionCall(Handle::new(Call {
+            call_name: c.call_name.clone(),
+            args: c.args.iter().map(|x| bind_defines_expr(x, env, locals)).collect(),
+        })),
+        Expr::Pair(p) => Expr::Pair(Handle::new((
+            bind_defines_expr(&p.0, env, locals),
+            bind_defines_expr(&p.1, env, locals),
+        ))),
+        Expr::Tuple(t) => Expr::Tuple(t.iter().map(|x| bind_defines_expr(x, env, locals)).collect()),
+        Expr::Dict(entries, rest) => Expr::Dict(
+            entries
+                .iter()
+                .map(|(k, x)| (k.clone(), bind_defines_expr(x, env, locals)))
+                .collect(),
+            rest.as_ref().map(|x| Handle::new(bind_defines_expr(x, env, locals))),
+        ),
+    }
+}
+
+fn bind_defines_goal(this: &Goal, env: &Handle<Scope>, locals: &HashSet<Handle<Symbol>>) -> Goal {
+    match this {
+        Goal::Fact(query) => Goal::Fact(FactQuery {
+            name: query.name.clone(),
+            args: query.args.iter().map(|x| bind_defines_expr(x, env, locals)).collect(),
+        }),
+        Goal::Not(goal) => Goal::Not(Handle::new(bind_defines_goal(goal, env, locals))),
+        Goal::Or(goals) => Goal::Or(bind_defines(goals, env, locals)),
+        Goal::Aggregate(a) => Goal::Aggregate(Handle::new(Aggregate {
+            op: a.op,
+            result: bind_defines_expr(&a.result, env, locals),
+            value: a.value.as_ref().map(|x| bind_defines_expr(x, env, locals)),
+            goal: bind_defines_goal(&a.goal, env, locals),
+            group: a.group.clone(),
+        })),
+    }
+}
+
+/// Replaces the variables naming defined constants with their values,
+/// unless they are one of the `locals`.
+fn bind_defines(this: &[Goal], env: &Handle<Scope>, locals: &HashSet<Handle<Symbol>>) -> Handle<[Goal]> {
+    this.iter().map(|goal| bind_defines_goal(goal, env, locals)).collect()
+}
+
+pub(crate) fn expr_variables(this: &Expr, record: &mut HashSet<Handle<Symbol>>) {
+    match this {
+        Expr::Value(_) => {}
+        Expr::Variable(k) => {
+            if k.0.as_str() != "_" {
+                record.insert(k.clone());
+            }
+        }
+        Expr::FunctionCall(c) => c.args.iter().for_each(|x| expr_variables(x, record)),
+        Expr::Pair(p) => {
+            expr_variables(&p.0, record);
+            expr_variables(&p.1, record);
+        }
+        Expr::Tuple(t) => t.iter().for_each(|x| expr_variables(x, record)),
+        Expr::Dict(entries, rest) => {
+            entries.iter().for_each(|(_, x)| expr_variables(x, record));
+            rest.iter().for_each(|x| expr_variables(x, record));
+        }
+    }
+}
+
+pub(crate) fn goal_variables(this: &Goal, record: &mut HashSet<Handle<Symbol>>) {
+    match this {
+        Goal::Fact(query) => query.args.iter().for_each(|x| expr_variables(x, record)),
+        Goal::Not(goal) => goal_variables(goal, record),
+        Goal::Or(goals) => goals.iter().for_each(|x| goal_variables(x, record)),
+        Goal::Aggregate(a) => {
+            expr_variables(&a.result, record);
+            goal_variables(&a.goal, record);
+        }
+    }
+}
 
-use super::eval::eval_value;
+fn group_goal(this: &Goal, outside: &HashSet<Handle<Symbol>>) -> Goal {
+    match this {
+        Goal::Fact(_) => this.clone(),
+        Goal::Not(goal) => Goal::Not(Handle::new(group_goal(goal, outside))),
+        Goal::Or(goals) => Goal::Or(goals.iter().map(|x| group_goal(x, outside)).collect()),
+        Goal::Aggregate(a) => {
+            let mut inner = HashSet::new();
+            goal_variables(&a.goal, &mut inner);
+            // the result and the aggregated term are never grouped by
+            let mut own = HashSet::new();
+            expr_variables(&a.result, &mut own);
+            if let Some(x) = &a.value {
+                expr_variables(x, &mut own);
+            }
+            let group = inner
+                .into_iter()
+                .filter(|k| outside.contains(k) && !own.contains(k))
+                .collect();
+            Goal::Aggregate(Handle::new(Aggregate {
+                op: a.op,
+                result: a.result.clone(),
+                value: a.value.clone(),
+                goal: group_goal(&a.goal, outside),
+                group,
+            }))
+        }
+    }
+}
+
+/// Groups each aggregate by the remaining variables of its goal which
+/// are one of the `locals` or used by the other goals.
+fn group_aggregates(this: &[Goal], locals: &HashSet<Handle<Symbol>>) -> Handle<[Goal]> {
+    this.iter()
+        .enumerate()
+        .map(|(i, goal)| {
+            let mut outside = locals.clone();
+            this.iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .for_each(|(_, x)| goal_variables(x, &mut outside));
+            group_goal(goal, &outside)
+        })
+        .collect()
+}
+
+fn uint_from_gast(input: &GAst) -> Option<usize> {
+    match Value::from_gast(input)? {
+        Value::Uint(x) => Some(x as usize),
+        _ => None,
+    }
+}
 
 pub trait Loader {
-    fn load(&mut self, env: &Handle<Scope>, input: &GAst) -> Option<()>;
+    /// Returns `None` if the input is not a definition of this kind.
+    fn load(&mut self, db: &Database, env: &Handle<Scope>, input: &GAst) -> Option<Result<(), Error>>;
 }
 
 impl Loader for FactRecord {
-    fn load(&mut self, env: &Handle<Scope>, input: &GAst) -> Option<()> {
+    fn load(&mut self, db: &Database, env: &Handle<Scope>, input: &GAst) -> Option<Result<(), Error>> {
         let r = FACT_PATTERN.catch(input).ok()?;
         let r: HashMap<Handle<Symbol>, Capture> = r.into_iter().collect();
 
@@ -30,70 +194,175 @@ impl Loader for FactRecord {
         let name = name.get_const()?.get_sym()?;
 
         let exprs = r.get(&Symbol::new("exprs")).unwrap().get_many().unwrap();
-        let exprs: Option<Handle<[_]>> = exprs
-            .iter()
-            .map(|x| Expr::from_gast(x).map(|x| eval_value(&x, env)).flatten())
-            .collect();
-        let exprs = exprs?;
+        let exprs: Option<Vec<_>> = exprs.iter().map(Expr::from_gast).collect();
+        let exprs: Result<Handle<[_]>, Error> = exprs?.iter().map(|x| eval_value(x, db, env)).collect();
+        let exprs = match exprs {
+            Ok(x) => x,
+            Err(e) => return Some(Err(e)),
+        };
 
         let key = (name, exprs.len());
-        if let Some(x) = self.0.get_mut(&key) {
-            x.0.push(ValueLine(exprs));
+        let bag = db.bags.read().unwrap().0.contains(&key);
+        let table = self.0.entry(key.clone()).or_insert_with(|| {
+            let mut r = ValueTable::new(key.1);
+            let indexes = db.indexes.write().unwrap().0.remove(&key);
+            indexes.into_iter().flatten().for_each(|x| r.add_index(&x));
+            Handle::new(r)
+        });
+        let table = Handle::make_mut(table);
+        if bag {
+            table.push(ValueLine(exprs));
         } else {
-            self.0.insert(key, ValueTable(vec![ValueLine(exprs)]));
+            table.insert(ValueLine(exprs));
         }
-        Some(())
+        Some(Ok(()))
     }
 }
 
-impl Loader for RuleRecord {
-    fn load(&mut self, _: &Handle<Scope>, input: &GAst) -> Option<()> {
-        let r = RULE_PATTERN.catch(input).ok()?;
-        let r: HashMap<Handle<Symbol>, Capture> = r.into_iter().collect();
+/// The relation of a rule and its body, `None` if the input is not a rule.
+fn rule_from_gast(env: &Handle<Scope>, input: &GAst) -> Option<(Key, RuleBody)> {
+    let r = RULE_PATTERN.catch(input).ok()?;
+    let r: HashMap<Handle<Symbol>, Capture> = r.into_iter().collect();
 
-        let prarms = r.get(&Symbol::new("prarms")).unwrap().get_one().unwrap();
-        let prarms = RULE_PARAMS_PATTERN.catch(prarms).ok()?;
-        let prarms: HashMap<Handle<Symbol>, Capture> = prarms.into_iter().collect();
+    let prarms = r.get(&Symbol::new("prarms")).unwrap().get_one().unwrap();
+    let prarms = RULE_PARAMS_PATTERN.catch(prarms).ok()?;
+    let prarms: HashMap<Handle<Symbol>, Capture> = prarms.into_iter().collect();
 
-        let name = prarms.get(&Symbol::new("name")).unwrap().get_one().unwrap();
-        let name = name.get_const()?.get_sym()?;
+    let name = prarms.get(&Symbol::new("name")).unwrap().get_one().unwrap();
+    let name = name.get_const()?.get_sym()?;
 
-        let args = prarms
-            .get(&Symbol::new("args"))
-            .unwrap()
-            .get_many()
-            .unwrap();
-        let args: Option<Handle<[_]>> = args.iter().map(Pattern::from_gast).collect();
-        let args = args?;
+    let args = prarms
+        .get(&Symbol::new("args"))
+        .unwrap()
+        .get_many()
+        .unwrap();
+    let args: Option<Handle<[_]>> = args.iter().map(Pattern::from_gast).collect();
+    let args = args?;
 
-        let exprs = r.get(&Symbol::new("exprs")).unwrap().get_many().unwrap();
-        let exprs: Option<Handle<[_]>> = exprs.iter().map(FactQuery::from_gast).collect();
-        let exprs = exprs?;
+    let exprs = r.get(&Symbol::new("exprs")).unwrap().get_many().unwrap();
+    let exprs: Option<Box<[_]>> = exprs.iter().map(Goal::from_gast).collect();
+    let exprs = exprs?;
+    let mut locals = HashSet::new();
+    args.iter().for_each(|x| pattern_variables(x, &mut locals));
+    let exprs = bind_defines(&exprs, env, &locals);
+    let exprs = group_aggregates(&exprs, &locals);
 
-        let key = (name, prarms.len());
-        let value = RuleBody {
-            prarms: args,
-            bodys: exprs,
-        };
+    let key = (name, args.len());
+    let value = RuleBody {
+        prarms: args,
+        bodys: exprs,
+    };
+    Some((key, value))
+}
 
-        if let Some(x) = self.0.get_mut(&key) {
-            x.0.push(value);
-        } else {
-            self.0.insert(key, RuleTable(vec![value]));
+impl Loader for RuleRecord {
+    /// The rule is rejected, and the rules left as they were,
+    /// if the program is no longer stratifiable with it.
+    fn load(&mut self, _: &Database, env: &Handle<Scope>, input: &GAst) -> Option<Result<(), Error>> {
+        let (key, value) = rule_from_gast(env, input)?;
+        self.0.entry(key.clone()).or_default().0.push(value);
+        let r = check_stratified(self, &key);
+        if r.is_err() {
+            let table = self.0.get_mut(&key).unwrap();
+            table.0.pop();
+            if table.0.is_empty() {
+                self.0.remove(&key);
+            }
         }
-        Some(())
+        Some(r)
     }
 }
 
 
-    fn database_load(this: &Handle<Database>, env: &Handle<Scope>, input: &GAst) -> Option<()> {
-        let r = this.facts.write().unwrap().load(env, input);
-        if r.is_some() {
-            return Some(());
+    /// Returns `None` if the input is not a definition.
+    fn database_load(this: &Handle<Database>, env: &Handle<Scope>, input: &GAst) -> Option<Result<(), Error>> {
+        let r = this.facts.write().unwrap().load(this, env, input);
+        if let Some(r) = r {
+            if r.is_ok() {
+                this.clear_derived();
+            }
+            return Some(r);
         }
-        let r = this.rules.write().unwrap().load(env, input);
-        if r.is_some() {
-            return Some(());
+        let r = this.rules.write().unwrap().load(this, env, input);
+        if let Some(r) = r {
+            if r.is_ok() {
+                this.clear_derived();
+            }
+            return Some(r);
+        }
+        if let Ok(capture) = TABLE_PATTERN.catch(input) {
+            let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+            let name = capture
+                .get(&Symbol::new("name"))
+                .unwrap()
+                .get_one()
+                .unwrap()
+                .get_const()?
+                .get_sym()?;
+            let arity = capture.get(&Symbol::new("arity")).unwrap().get_one().unwrap();
+            let arity = match uint_from_gast(arity) {
+                Some(x) => x,
+                None => return Some(Err(Error::InvalidForm)),
+            };
+            this.tabled.write().unwrap().0.insert((name, arity));
+            this.clear_derived();
+            return Some(Ok(()));
+        }
+        if let Ok(capture) = BAG_PATTERN.catch(input) {
+            let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+            let name = capture
+                .get(&Symbol::new("name"))
+                .unwrap()
+                .get_one()
+                .unwrap()
+                .get_const()?
+                .get_sym()?;
+            let arity = capture.get(&Symbol::new("arity")).unwrap().get_one().unwrap();
+            return Some(match uint_from_gast(arity) {
+                Some(arity) => {
+                    this.set_bag(&name.0, arity);
+                    Ok(())
+                }
+                None => Err(Error::InvalidForm),
+            });
+        }
+        if let Ok(capture) = RETRACT_PATTERN.catch(input) {
+            let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+            let name = capture
+                .get(&Symbol::new("name"))
+                .unwrap()
+                .get_one()
+                .unwrap()
+                .get_const()?
+                .get_sym()?;
+            let exprs = capture.get(&Symbol::new("exprs")).unwrap().get_many().unwrap();
+            let exprs: Option<Vec<_>> = exprs.iter().map(Expr::from_gast).collect();
+            let exprs: Result<Vec<_>, Error> = exprs?
+                .iter()
+                .map(|x| eval_calls(&bind_defines_expr(x, env, &HashSet::new()), this, env))
+                .collect();
+            return Some(exprs.map(|x| {
+                this.retract(&name.0, &x);
+            }));
+        }
+        if let Ok(capture) = INDEX_PATTERN.catch(input) {
+            let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+            let name = capture
+                .get(&Symbol::new("name"))
+                .unwrap()
+                .get_one()
+                .unwrap()
+                .get_const()?
+                .get_sym()?;
+            let arity = capture.get(&Symbol::new("arity")).unwrap().get_one().unwrap();
+            let cols = capture.get(&Symbol::new("cols")).unwrap().get_one().unwrap();
+            let cols = QUOTED_LIST_PATTERN.catch(cols).ok()?;
+            let (_, cols) = cols.first()?;
+            let cols: Option<Vec<_>> = cols.get_many()?.iter().map(uint_from_gast).collect();
+            return Some(match (uint_from_gast(arity), cols) {
+                (Some(arity), Some(cols)) => this.add_index(&name.0, arity, &cols),
+                _ => Err(Error::InvalidForm),
+            });
         }
         if let Ok(capture) = DEFINE_PATTERN.catch(input) {
             let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
@@ -110,16 +379,18 @@ impl Loader for RuleRecord {
                 .get_one()
                 .unwrap();
             let expr = Expr::from_gast(expr)?;
-            let value = eval_value(&expr, env)?;
-            env.set(&name, &value);
-            Some(())
+            let r = eval_value(&expr, this, env).map(|value| env.set(&name, &value));
+            Some(r)
         } else {
             None
         }
     }
 
-pub fn apply_query(env: &Handle<Database>, scope: &Handle<Scope>, input: &GAst) -> Option<SimpleScope> {
-    // parse
+/// The values of the query params bound by a solution, in the order of the params.
+pub type Row = Vec<(Handle<Symbol>, Value)>;
+
+/// The params and goals of a query, `None` if the input is not a query.
+fn query_from_gast(input: &GAst) -> Option<(Vec<Handle<Symbol>>, Vec<Goal>)> {
     let capture = QUERY_PATTERN.catch(input).ok()?;
     let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
     let prarms = capture
@@ -130,40 +401,111 @@ pub fn apply_query(env: &Handle<Database>, scope: &Handle<Scope>, input: &GAst)
     let prarms = QUERY_PARAMS_PATTERN.catch(prarms).ok()?;
     let (cap_name, args) = prarms.first()?;
     debug_assert_eq!(cap_name.0.as_str(), "args");
-    let args: Option<Box<[_]>> = args
+    let args: Option<Vec<_>> = args
         .get_many()?
         .iter()
         .map(|x| x.get_const()?.get_sym())
         .collect();
     let args = args?;
-    let exprs: Option<Box<[_]>> = capture
+    let exprs: Option<Vec<_>> = capture
         .get(&Symbol::new("exprs"))
         .unwrap()
         .get_many()
         .unwrap()
         .iter()
-        .map(FactQuery::from_gast)
+        .map(Goal::from_gast)
         .collect();
-    let exprs = exprs?;
+    Some((args, exprs?))
+}
+
+/// Parses the query and returns its solutions lazily, one row of
+/// bindings of the query params per solution, or the error stopping the evaluation.
+/// Stopping the iteration early skips the remaining work.
+/// Errors with `InvalidForm` if the input is not a query.
+pub fn query_iter(
+    env: &Handle<Database>,
+    scope: &Handle<Scope>,
+    input: &GAst,
+) -> Result<impl Iterator<Item = Result<Row, Error>>, Error> {
+    query_iter_with(env, scope, input, env.evaluation)
+}
+
+/// Whether the answers of the goals keep duplicates, that is some goal,
+/// or a goal inside one, is on a bag relation.
+pub(crate) fn is_bag_query(goals: &[Goal], bags: &BagRecord) -> bool {
+    let mut record = vec![];
+    goals.iter().for_each(|x| goal_dependencies(x, false, &mut record));
+    record.iter().any(|(k, _)| bags.0.contains(k))
+}
+
+/// As `query_iter`, with the evaluation given for this query
+/// instead of the one of the database.
+pub fn query_iter_with(
+    env: &Handle<Database>,
+    scope: &Handle<Scope>,
+    input: &GAst,
+    evaluation: Evaluation,
+) -> Result<impl Iterator<Item = Result<Row, Error>>, Error> {
+    let (args, exprs) = query_from_gast(input).ok_or(Error::InvalidForm)?;
+
+    let locals = args.iter().cloned().collect();
+    let exprs = bind_defines(&exprs, scope, &locals);
+    let exprs = group_aggregates(&exprs, &locals);
 
     // init env: new scope
-    let capture: HashMap<_, _> = args
-        .iter()
-        .map(|x| (x.clone(), Expr::Variable(x.clone())))
-        .collect();
-    let capture = RwLock::new(capture);
-    let new_scope = scope.new_level(SimpleScope::new());
+    let mut capture = HashMap::new();
+    let exprs = rename(&exprs, &mut capture);
+    let exprs: Handle<[Goal]> = if env.fixed_goal_order {
+        exprs
+    } else {
+        plan(&exprs, &HashSet::new(), env).into()
+    };
+    let new_scope = Scope::new();
+    // the rows are a set, unless the query is on a bag relation
+    let bag = is_bag_query(&exprs, &env.bags.read().unwrap());
     // eval
-    exprs
-        .par_iter()
-        .try_for_each(|x| query_fact(x, env, &new_scope, &capture))?;
-    Some(new_scope.this_level.clone())
+    let (env, exprs) = match evaluation {
+        Evaluation::TopDown => (env.clone(), exprs),
+        Evaluation::BottomUp => (materialized(env)?, exprs),
+        Evaluation::Magic => {
+            let params: Vec<_> = args.iter().filter_map(|k| capture.get(k).cloned()).collect();
+            let bags = env.bags.read().unwrap().clone();
+            let (rules, bags, goal) = magic_program(&env.rules.read().unwrap(), &bags, &exprs, &params);
+            let goals: Handle<[Goal]> = vec![Goal::Fact(goal)].into();
+            (materialize_rules(env, &rules, &bags)?, goals)
+        }
+    };
+    let r = query_goals(&exprs, &env, &new_scope);
+    // one row per solution, holding the query params bound to values
+    let mut seen = HashSet::new();
+    let r = r
+        .map(move |scope| {
+            let scope = scope?;
+            let row = args
+                .iter()
+                .filter_map(|k| {
+                    let x = capture.get(k)?;
+                    let v = resolve(&Expr::Variable(x.clone()), &scope).to_value()?;
+                    Some((k.clone(), v))
+                })
+                .collect::<Row>();
+            Ok(row)
+        })
+        .filter(move |row| match row {
+            Ok(row) => bag || seen.insert(row.clone()),
+            Err(_) => true,
+        });
+    Ok(r)
+}
+
+pub fn apply_query(env: &Handle<Database>, scope: &Handle<Scope>, input: &GAst) -> Result<Vec<Row>, Error> {
+    query_iter(env, scope, input)?.collect()
 }
 
 
-pub fn repl_eval(db: &Handle<Database>, env: &Handle<Scope>, input: &GAst) -> Option<Option<SimpleScope>> {
-    if database_load(db, env, input).is_some() {
-        return Some(None);
+pub fn repl_eval(db: &Handle<Database>, env: &Handle<Scope>, input: &GAst) -> Result<Option<Vec<Row>>, Error> {
+    if let Some(r) = database_load(db, env, input) {
+        return r.map(|_| None);
     }
     apply_query(db, env, input).map(Some)
-}
\ No newline at end of file
+}
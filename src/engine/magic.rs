@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{
+    fact::BagRecord,
+    rule::{Expr, FactQuery, Goal, Pattern, RuleBody, RuleRecord, RuleTable},
+    value::{Handle, Value},
+};
+
+use super::{
+    load::{is_bag_query, pattern_variables},
+    planner::{bind_goal, is_bound},
+    query::fresh_variable,
+    stratify::{goal_dependencies, Key},
+};
+
+/// The args of a relation known to be bound when it is called, `true` if bound.
+type Adornment = Vec<bool>;
+
+fn adornment_name(this: &[bool]) -> String {
+    this.iter().map(|x| if *x { 'b' } else { 'f' }).collect()
+}
+
+fn adorned_name(name: &Handle<Symbol>, adornment: &[bool]) -> Handle<Symbol> {
+    Handle::new(Symbol::new(&format!("{}#{}", name, adornment_name(adornment))))
+}
+
+fn magic_name(name: &Handle<Symbol>, adornment: &[bool]) -> Handle<Symbol> {
+    Handle::new(Symbol::new(&format!("magic#{}#{}", name, adornment_name(adornment))))
+}
+
+fn pattern_expr(this: &Pattern) -> Expr {
+    match this {
+        Pattern::Ignore => Expr::Variable(Handle::new(Symbol::new("_"))),
+        Pattern::Variable(k) => Expr::Variable(k.clone()),
+        Pattern::Constant(c) => Expr::Value(c.clone()),
+        Pattern::Tuple(patterns) => Expr::Tuple(patterns.iter().map(pattern_expr).collect()),
+        Pattern::List(patterns, extend) => {
+            let tail = extend.as_ref().map_or(Expr::Value(Value::Nil), |x| pattern_expr(x));
+            patterns
+                .iter()
+                .rev()
+                .fold(tail, |tail, item| Expr::Pair(Handle::new((pattern_expr(item), tail))))
+        }
+        Pattern::Dict(patterns, rest) => Expr::Dict(
+            patterns.iter().map(|(k, x)| (k.clone(), pattern_expr(x))).collect(),
+            rest.as_ref().map(|x| Handle::new(pattern_expr(x))),
+        ),
+    }
+}
+
+fn add_rule(program: &mut RuleRecord, key: Key, body: RuleBody) {
+    program.0.entry(key).or_insert_with(|| RuleTable(vec![])).0.push(body);
+}
+
+struct Rewrite<'a> {
+    rules: &'a RuleRecord,
+    bags: &'a BagRecord,
+    program: RuleRecord,
+    /// The relations of the program keeping duplicates.
+    program_bags: BagRecord,
+    done: HashSet<(Key, Adornment)>,
+    todo: Vec<(Key, Adornment)>,
+    originals: HashSet<Key>,
+}
+
+impl Rewrite<'_> {
+    /// Keeps the relation and the ones it depends on as they are.
+    fn original(&mut self, key: Key) {
+        if !self.rules.0.contains_key(&key) || !self.originals.insert(key.clone()) {
+            return;
+        }
+        let table = self.rules.0[&key].clone();
+        for body in table.0.iter() {
+            let mut record = vec![];
+            body.bodys
+                .iter()
+                .for_each(|goal| goal_dependencies(goal, false, &mut record));
+            record.into_iter().for_each(|(k, _)| self.original(k));
+        }
+        self.program.0.insert(key, table);
+    }
+
+    /// Renames the calls of relations with bound args to their adorned relation,
+    /// each with a magic rule deriving its bound args from the goals before it.
+    /// Relations called without bound args or inside another goal are kept whole.
+    fn rewrite_body(&mut self, goals: &[Goal], guard: Option<Goal>, bound: HashSet<Handle<Symbol>>) -> Vec<Goal> {
+        let mut bound = bound;
+        let mut r: Vec<Goal> = guard.into_iter().collect();
+        for goal in goals {
+            match goal {
+                Goal::Fact(query) if self.rules.0.contains_key(&(query.name.clone(), query.args.len())) => {
+                    let key = (query.name.clone(), query.args.len());
+                    let adornment: Adornment = query.args.iter().map(|x| is_bound(x, &bound)).collect();
+                    if adornment.iter().any(|x| *x) {
+                        let mut head = vec![];
+                        let mut bodys = r.clone();
+                        for x in query.args.iter().zip(adornment.iter()).filter(|(_, b)| **b).map(|(x, _)| x) {
+                            match x {
+                                Expr::Variable(k) => head.push(Pattern::Variable(k.clone())),
+                                _ => {
+                                    let k = fresh_variable(&Handle::new(Symbol::new("_")));
+                                    head.push(Pattern::Variable(k.clone()));
+                                    bodys.push(Goal::Fact(FactQuery {
+                                        name: Handle::new(Symbol::new("=")),
+                                        args: vec![Expr::Variable(k), x.clone()].into(),
+                                    }));
+                                }
+                            }
+                        }
+                        let magic = (magic_name(&query.name, &adornment), head.len());
+                        add_rule(
+                            &mut self.program,
+                            magic,
+                            RuleBody {
+                                prarms: head.into(),
+                                bodys: bodys.into(),
+                            },
+                        );
+                        r.push(Goal::Fact(FactQuery {
+                            name: adorned_name(&query.name, &adornment),
+                            args: query.args.clone(),
+                        }));
+                        if self.done.insert((key.clone(), adornment.clone())) {
+                            self.todo.push((key, adornment));
+                        }
+                    } else {
+                        self.original(key);
+                        r.push(goal.clone());
+                    }
+                }
+                _ => {
+                    let mut record = vec![];
+                    goal_dependencies(goal, false, &mut record);
+                    record.into_iter().for_each(|(k, _)| self.original(k));
+                    r.push(goal.clone());
+                }
+            }
+            bind_goal(goal, &mut bound);
+        }
+        r
+    }
+
+    /// The rules of the adorned relation, each only deriving the tuples
+    /// whose bound args are in its magic relation.
+    /// The adorned relation of a bag is a bag, its magic relation a set.
+    fn adorn(&mut self, key: Key, adornment: Adornment) {
+        if self.bags.0.contains(&key) {
+            self.program_bags.0.insert((adorned_name(&key.0, &adornment), key.1));
+        }
+        let table = self.rules.0[&key].clone();
+        for body in table.0.iter() {
+            let mut bound = HashSet::new();
+            let mut args = vec![];
+            for (x, _) in body.prarms.iter().zip(adornment.iter()).filter(|(_, b)| **b) {
+                pattern_variables(x, &mut bound);
+                args.push(pattern_expr(x));
+            }
+            let guard = Goal::Fact(FactQuery {
+                name: magic_name(&key.0, &adornment),
+                args: args.into(),
+            });
+            let bodys = self.rewrite_body(&body.bodys, Some(guard), bound);
+            add_rule(
+                &mut self.program,
+                (adorned_name(&key.0, &adornment), key.1),
+                RuleBody {
+                    prarms: body.prarms.clone(),
+                    bodys: bodys.into(),
+                },
+            );
+        }
+    }
+}
+
+/// Rewrites the rules with the magic-sets transformation for the query goals,
+/// so that bottom-up evaluation only derives the tuples relevant to them.
+/// The query becomes a rule over `params`, the returned goal gives its answers.
+/// Also returns the relations of the program keeping duplicates, given `bags` those of `rules`.
+pub fn magic_program(
+    rules: &RuleRecord,
+    bags: &BagRecord,
+    goals: &[Goal],
+    params: &[Handle<Symbol>],
+) -> (RuleRecord, BagRecord, FactQuery) {
+    let mut this = Rewrite {
+        rules,
+        bags,
+        program: RuleRecord::default(),
+        program_bags: bags.clone(),
+        done: HashSet::new(),
+        todo: vec![],
+        originals: HashSet::new(),
+    };
+    let bodys = this.rewrite_body(goals, None, HashSet::new());
+    while let Some((key, adornment)) = this.todo.pop() {
+        this.adorn(key, adornment);
+    }
+    let name = Handle::new(Symbol::new("query#"));
+    if is_bag_query(goals, bags) {
+        this.program_bags.0.insert((name.clone(), params.len()));
+    }
+    add_rule(
+        &mut this.program,
+        (name.clone(), params.len()),
+        RuleBody {
+            prarms: params.iter().cloned().map(Pattern::Variable).collect(),
+            bodys: bodys.into(),
+        },
+    );
+    let goal = FactQuery {
+        name,
+        args: params.iter().cloned().map(Expr::Variable).collect(),
+    };
+    (this.program, this.program_bags, goal)
+}
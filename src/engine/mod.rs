@@ -1,6 +1,15 @@
+pub mod aggregate;
+pub mod bottomup;
+pub mod builtin;
 pub mod environment;
+pub mod error;
 pub mod eval;
+pub mod join;
 pub mod load;
+pub mod magic;
 mod parser;
+pub mod planner;
 pub mod query;
+pub mod stratify;
+pub mod tabling;
 mod utils;
@@ -4,8 +4,8 @@ use sexpr_ir::gast::{constant::Constant, symbol::Symbol, GAst};
 use sexpr_process::capture::{Capture, Catch};
 
 use crate::structs::{
-    rule::{Call, Expr, FactQuery, Pattern},
-    value::{Handle, Value},
+    rule::{Aggregate, AggregateOp, Call, Entries, Expr, FactQuery, Goal, Pattern},
+    value::{Handle, Pair, Value},
 };
 
 use super::utils::*;
@@ -43,6 +43,85 @@ fn symbol_from_sexpr(i: &GAst) -> Option<Handle<Symbol>> {
     i.get_const()?.get_sym()
 }
 
+fn quoted_value_from_gast(i: &GAst) -> Option<Value> {
+    match i {
+        GAst::Const(x) => simple_value_from_gast(x),
+        GAst::List(_) => {
+            let (items, tail) = if let Ok(capture) = QUOTED_PAIR_PATTERN.catch(i) {
+                let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+                let items = capture
+                    .get(&Symbol::new("items"))
+                    .unwrap()
+                    .get_many()
+                    .unwrap()
+                    .clone();
+                let tail = capture.get(&Symbol::new("tail")).unwrap().get_one().unwrap();
+                (items, quoted_value_from_gast(tail)?)
+            } else {
+                let capture = QUOTED_LIST_PATTERN.catch(i).ok()?;
+                let (cap_name, capture) = capture.first()?;
+                debug_assert_eq!(cap_name.0.as_str(), "items");
+                (capture.get_many().unwrap().clone(), Value::Nil)
+            };
+            let items: Option<Vec<_>> = items.iter().map(quoted_value_from_gast).collect();
+            let r = items?
+                .into_iter()
+                .rev()
+                .fold(tail, |tail, item| Value::Pair(Handle::new(Pair(item, tail))));
+            Some(r)
+        }
+    }
+}
+
+/// A literal of a type without a constant of its own, as `(bigint "12")`.
+fn typed_literal_from_gast(i: &GAst) -> Option<Value> {
+    let capture = TYPED_LITERIAL_PATTERN.catch(i).ok()?;
+    let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+    let tp = symbol_from_sexpr(capture.get(&Symbol::new("type")).unwrap().get_one().unwrap())?;
+    match capture.get(&Symbol::new("text")).unwrap().get_one().unwrap() {
+        GAst::Const(Constant::Str(text)) => Value::from_literal(tp.0.as_str(), text),
+        _ => None,
+    }
+}
+
+fn list_expr(items: Vec<Expr>, tail: Expr) -> Expr {
+    items
+        .into_iter()
+        .rev()
+        .fold(tail, |tail, item| Expr::Pair(Handle::new((item, tail))))
+}
+
+/// The entries of a dict form, each `("key" . item)` with a distinct key,
+/// and the item after the dot of the form if it has one.
+fn dict_from_capture<T: FromGast<Target = T>>(
+    capture: Vec<(Handle<Symbol>, Capture)>,
+) -> Option<(Entries<T>, Option<T>)> {
+    let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+    let entries = capture
+        .get(&Symbol::new("entries"))
+        .unwrap()
+        .get_many()
+        .unwrap();
+    let rest = match capture.get(&Symbol::new("rest")) {
+        Some(x) => Some(T::from_gast(x.get_one().unwrap())?),
+        None => None,
+    };
+    let mut r: Vec<(Handle<String>, T)> = vec![];
+    for entry in entries.iter() {
+        let entry: HashMap<Handle<Symbol>, Capture> = DICT_ENTRY_PATTERN.catch(entry).ok()?.into_iter().collect();
+        let key = match entry.get(&Symbol::new("key")).unwrap().get_one().unwrap() {
+            GAst::Const(Constant::Str(k)) => k.clone(),
+            _ => return None,
+        };
+        if r.iter().any(|(k, _)| k == &key) {
+            return None;
+        }
+        let value = T::from_gast(entry.get(&Symbol::new("value")).unwrap().get_one().unwrap())?;
+        r.push((key, value));
+    }
+    Some((r.into(), rest))
+}
+
 /////////////////////////////
 
 impl FromGast for Value {
@@ -55,10 +134,9 @@ impl FromGast for Value {
                 if let Ok(capture) = SYMBOL_LITERIAL_PATTERN.catch(input) {
                     let (cap_name, capture) = capture.first()?;
                     debug_assert_eq!(cap_name.0.as_str(), "sym");
-                    let capture = capture.get_one().unwrap().get_const()?.get_sym()?;
-                    Some(Value::Sym(capture))
+                    quoted_value_from_gast(capture.get_one().unwrap())
                 } else {
-                    None
+                    typed_literal_from_gast(input)
                 }
             }
         }
@@ -102,11 +180,48 @@ impl FromGast for Expr {
                 }
             }
             GAst::List(_) => {
-                if let Ok(capture) = SYMBOL_LITERIAL_PATTERN.catch(input) {
+                if SYMBOL_LITERIAL_PATTERN.catch(input).is_ok() {
+                    Some(Expr::Value(Value::from_gast(input)?))
+                } else if let Some(x) = typed_literal_from_gast(input) {
+                    Some(Expr::Value(x))
+                } else if let Ok(capture) = TUPLE_PATTERN_PATTERN.catch(input) {
                     let (cap_name, capture) = capture.first()?;
-                    debug_assert_eq!(cap_name.0.as_str(), "sym");
-                    let capture = capture.get_one().unwrap().get_const()?.get_sym()?;
-                    Some(Expr::Value(Value::Sym(capture)))
+                    debug_assert_eq!(cap_name.0.as_str(), "args");
+                    let capture: Option<_> = capture
+                        .get_many()
+                        .unwrap()
+                        .iter()
+                        .map(Expr::from_gast)
+                        .collect();
+                    let capture = capture?;
+                    Some(Expr::Tuple(capture))
+                } else if let Ok(capture) = LIST_HAS_EXTEND_PATTERN_PATTERN.catch(input) {
+                    let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+                    let args = capture
+                        .get(&Symbol::new("args"))
+                        .unwrap()
+                        .get_many()
+                        .unwrap();
+                    let extend = capture
+                        .get(&Symbol::new("extend"))
+                        .unwrap()
+                        .get_one()
+                        .unwrap();
+                    let args: Option<Vec<_>> = args.iter().map(Expr::from_gast).collect();
+                    Some(list_expr(args?, Expr::from_gast(extend)?))
+                } else if let Ok(capture) = LIST_PATTERN_PATTERN.catch(input) {
+                    let (cap_name, capture) = capture.first()?;
+                    debug_assert_eq!(cap_name.0.as_str(), "args");
+                    let args: Option<Vec<_>> = capture
+                        .get_many()
+                        .unwrap()
+                        .iter()
+                        .map(Expr::from_gast)
+                        .collect();
+                    Some(list_expr(args?, Expr::Value(Value::Nil)))
+                } else if let Ok(capture) = DICT_PATTERN.catch(input).or_else(|_| DICT_HAS_REST_PATTERN.catch(input)) {
+                    let (entries, rest) = dict_from_capture::<Expr>(capture)?;
+                    Some(Expr::Dict(entries, rest.map(Handle::new)))
                 } else {
                     Call::from_gast(input).map(|x| Expr::FunctionCall(Handle::new(x)))
                 }
@@ -132,11 +247,10 @@ impl FromGast for Pattern {
                 }
             }
             GAst::List(_) => {
-                if let Ok(capture) = SYMBOL_LITERIAL_PATTERN.catch(input) {
-                    let (cap_name, capture) = capture.first()?;
-                    debug_assert_eq!(cap_name.0.as_str(), "sym");
-                    let capture = capture.get_one().unwrap().get_const()?.get_sym()?;
-                    Some(Pattern::Constant(Value::Sym(capture)))
+                if SYMBOL_LITERIAL_PATTERN.catch(input).is_ok() {
+                    Some(Pattern::Constant(Value::from_gast(input)?))
+                } else if let Some(x) = typed_literal_from_gast(input) {
+                    Some(Pattern::Constant(x))
                 } else if let Ok(capture) = TUPLE_PATTERN_PATTERN.catch(input) {
                     let (cap_name, capture) = capture.first()?;
                     debug_assert_eq!(cap_name.0.as_str(), "args");
@@ -175,6 +289,9 @@ impl FromGast for Pattern {
                         .collect();
                     let capture = capture?;
                     Some(Pattern::List(capture, None))
+                } else if let Ok(capture) = DICT_PATTERN.catch(input).or_else(|_| DICT_HAS_REST_PATTERN.catch(input)) {
+                    let (entries, rest) = dict_from_capture::<Pattern>(capture)?;
+                    Some(Pattern::Dict(entries, rest.map(Handle::new)))
                 } else {
                     None
                 }
@@ -206,3 +323,70 @@ impl FromGast for FactQuery {
         Some(FactQuery { name, args })
     }
 }
+
+fn count_from_gast(input: &GAst) -> Option<Aggregate> {
+    let capture = COUNT_GOAL_PATTERN.catch(input).ok()?;
+    let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+    let result = capture.get(&Symbol::new("result")).unwrap().get_one().unwrap();
+    let goal = capture.get(&Symbol::new("goal")).unwrap().get_one().unwrap();
+    Some(Aggregate {
+        op: AggregateOp::Count,
+        result: Expr::from_gast(result)?,
+        value: None,
+        goal: Goal::from_gast(goal)?,
+        group: vec![].into(),
+    })
+}
+
+fn aggregate_from_gast(input: &GAst) -> Option<Aggregate> {
+    let capture = AGGREGATE_GOAL_PATTERN.catch(input).ok()?;
+    let capture: HashMap<Handle<Symbol>, Capture> = capture.into_iter().collect();
+    let op = capture
+        .get(&Symbol::new("op"))
+        .unwrap()
+        .get_one()
+        .unwrap()
+        .get_const()?
+        .get_sym()?;
+    let op = match op.0.as_str() {
+        "sum" => AggregateOp::Sum,
+        "min" => AggregateOp::Min,
+        "max" => AggregateOp::Max,
+        "avg" => AggregateOp::Avg,
+        "collect" => AggregateOp::Collect,
+        _ => return None,
+    };
+    let result = capture.get(&Symbol::new("result")).unwrap().get_one().unwrap();
+    let value = capture.get(&Symbol::new("value")).unwrap().get_one().unwrap();
+    let goal = capture.get(&Symbol::new("goal")).unwrap().get_one().unwrap();
+    Some(Aggregate {
+        op,
+        result: Expr::from_gast(result)?,
+        value: Some(Expr::from_gast(value)?),
+        goal: Goal::from_gast(goal)?,
+        group: vec![].into(),
+    })
+}
+
+impl FromGast for Goal {
+    type Target = Self;
+
+    fn from_gast(input: &GAst) -> Option<Self::Target> {
+        if let Ok(capture) = NOT_GOAL_PATTERN.catch(input) {
+            let (cap_name, capture) = capture.first()?;
+            debug_assert_eq!(cap_name.0.as_str(), "goal");
+            let goal = Goal::from_gast(capture.get_one().unwrap())?;
+            Some(Goal::Not(Handle::new(goal)))
+        } else if let Ok(capture) = OR_GOAL_PATTERN.catch(input) {
+            let (cap_name, capture) = capture.first()?;
+            debug_assert_eq!(cap_name.0.as_str(), "goals");
+            let goals: Option<Handle<[_]>> = capture.get_many()?.iter().map(Goal::from_gast).collect();
+            Some(Goal::Or(goals?))
+        } else if let Some(r) = count_from_gast(input).or_else(|| aggregate_from_gast(input)) {
+            Some(Goal::Aggregate(Handle::new(r)))
+        } else {
+            // also a goal shaped as an aggregate, but without an inner goal
+            FactQuery::from_gast(input).map(Goal::Fact)
+        }
+    }
+}
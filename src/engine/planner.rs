@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{
+    plan::{BoundHead, RuleId},
+    rule::{Expr, FactQuery, Goal},
+    value::Handle,
+};
+
+use super::{
+    environment::Database,
+    load::{expr_variables, goal_variables, pattern_variables},
+    query::is_builtin_goal,
+    stratify::{recursive_relations, Key},
+};
+
+pub(crate) fn is_bound(this: &Expr, bound: &HashSet<Handle<Symbol>>) -> bool {
+    match this {
+        Expr::Value(_) => true,
+        Expr::Variable(k) => bound.contains(k),
+        Expr::FunctionCall(c) => c.args.iter().all(|x| is_bound(x, bound)),
+        Expr::Pair(p) => is_bound(&p.0, bound) && is_bound(&p.1, bound),
+        Expr::Tuple(t) => t.iter().all(|x| is_bound(x, bound)),
+        Expr::Dict(entries, rest) => {
+            entries.iter().all(|(_, x)| is_bound(x, bound)) && rest.iter().all(|x| is_bound(x, bound))
+        }
+    }
+}
+
+/// Adds the variables bound once the goal succeeds.
+pub(crate) fn bind_goal(this: &Goal, bound: &mut HashSet<Handle<Symbol>>) {
+    match this {
+        Goal::Fact(query) => match (query.name.0.as_str(), query.args.as_ref()) {
+            ("=", [left, right]) => {
+                if is_bound(left, bound) {
+                    expr_variables(right, bound);
+                } else if is_bound(right, bound) {
+                    expr_variables(left, bound);
+                }
+            }
+            ("!=" | "<" | "<=" | ">" | ">=", [_, _]) => {}
+            _ => query.args.iter().for_each(|x| expr_variables(x, bound)),
+        },
+        Goal::Aggregate(a) => {
+            expr_variables(&a.result, bound);
+            bound.extend(a.group.iter().cloned());
+        }
+        Goal::Not(_) | Goal::Or(_) => {}
+    }
+}
+
+pub(crate) fn has_call(this: &Expr) -> bool {
+    match this {
+        Expr::Value(_) | Expr::Variable(_) => false,
+        Expr::FunctionCall(_) => true,
+        Expr::Pair(p) => has_call(&p.0) || has_call(&p.1),
+        Expr::Tuple(t) => t.iter().any(has_call),
+        Expr::Dict(entries, rest) => entries.iter().any(|(_, x)| has_call(x)) || rest.iter().any(|x| has_call(x)),
+    }
+}
+
+/// Goals whose solutions depend on which of their variables are bound
+/// when they run: built-in comparisons, calls, negations, aggregates,
+/// disjunctions and goals on one of the `sensitive` relations.
+fn is_sensitive(this: &Goal, sensitive: &HashSet<Key>) -> bool {
+    match this {
+        Goal::Fact(query) => {
+            is_builtin_goal(query)
+                || query.args.iter().any(has_call)
+                || sensitive.contains(&(query.name.clone(), query.args.len()))
+        }
+        Goal::Not(_) | Goal::Or(_) | Goal::Aggregate(_) => true,
+    }
+}
+
+/// The relations whose solutions depend on which of their args are bound:
+/// native relations, and relations with a rule running a sensitive goal
+/// on a variable of its head, directly or through the relations it uses.
+fn sensitive_relations(db: &Database) -> HashSet<Key> {
+    let rules = db.rules.read().unwrap();
+    let facts = db.facts.read().unwrap();
+    let mut r: HashSet<Key> = db
+        .foreigns
+        .read()
+        .unwrap()
+        .0
+        .keys()
+        .filter(|k| !rules.0.contains_key(*k) && !facts.0.contains_key(*k))
+        .cloned()
+        .collect();
+    loop {
+        let found: Vec<Key> = rules
+            .0
+            .iter()
+            .filter(|(k, _)| !r.contains(*k))
+            .filter(|(_, table)| {
+                table.0.iter().any(|body| {
+                    let mut head = HashSet::new();
+                    body.prarms.iter().for_each(|x| pattern_variables(x, &mut head));
+                    body.bodys.iter().any(|goal| {
+                        let mut vars = HashSet::new();
+                        goal_variables(goal, &mut vars);
+                        is_sensitive(goal, &r) && vars.iter().any(|k| head.contains(k))
+                    })
+                })
+            })
+            .map(|(k, _)| k.clone())
+            .collect();
+        if found.is_empty() {
+            return r;
+        }
+        r.extend(found);
+    }
+}
+
+/// The sensitive relations of the database, found on first use.
+fn sensitive(db: &Database) -> Handle<HashSet<Key>> {
+    let generation = {
+        let record = db.plans.read().unwrap();
+        if let Some(r) = &record.sensitive {
+            return r.clone();
+        }
+        record.generation
+    };
+    let r = Handle::new(sensitive_relations(db));
+    let mut record = db.plans.write().unwrap();
+    if record.generation == generation {
+        record.sensitive = Some(r.clone());
+    }
+    r
+}
+
+/// The estimated number of solutions of a relation goal: the size of the
+/// facts, or of the bucket of the best index on the bound args.
+/// Relations defined by rules count as ten tuples to the power of their unbound args.
+fn estimate(this: &FactQuery, bound: &HashSet<Handle<Symbol>>, db: &Database) -> usize {
+    let k = (this.name.clone(), this.args.len());
+    let bound: Vec<bool> = this.args.iter().map(|x| is_bound(x, bound)).collect();
+    if db.rules.read().unwrap().0.contains_key(&k) {
+        return bound.iter().filter(|x| !**x).fold(1, |r, _| r * 10);
+    }
+    let record = db.facts.read().unwrap();
+    let table = match record.0.get(&k) {
+        Some(x) => x,
+        None => return 0,
+    };
+    table
+        .indexes
+        .iter()
+        .filter(|(cols, _)| cols.iter().all(|i| bound[*i]))
+        .map(|(_, index)| table.len() / index.0.len().max(1))
+        .min()
+        .unwrap_or(table.len())
+}
+
+/// Orders the goals of a conjunction, cheapest first given the variables
+/// bound so far. Goals sensitive to the order run with the same of their
+/// variables bound as in the written order, as soon as that holds.
+pub fn plan(goals: &[Goal], bound: &HashSet<Handle<Symbol>>, db: &Database) -> Vec<Goal> {
+    order(goals, bound, &HashSet::new(), db)
+        .into_iter()
+        .map(|i| goals[i].clone())
+        .collect()
+}
+
+/// As `plan`, for the body of a rule. The recursive calls are sensitive to
+/// the order too: run earlier with fewer args bound, a right recursion
+/// would become a left one, which does not end.
+/// The order found is reused by the calls binding the same variables of the head,
+/// until the database changes.
+pub(crate) fn plan_rule(
+    id: RuleId,
+    head: BoundHead,
+    goals: &[Goal],
+    bound: &HashSet<Handle<Symbol>>,
+    db: &Database,
+) -> Vec<Goal> {
+    let key = (id, head);
+    let (cached, generation) = {
+        let record = db.plans.read().unwrap();
+        (record.orders.get(&key).cloned(), record.generation)
+    };
+    let r = cached.unwrap_or_else(|| {
+        let recursive = recursive_relations(&db.rules.read().unwrap(), &key.0 .0);
+        let r: Handle<[usize]> = order(goals, bound, &recursive, db).into();
+        let mut record = db.plans.write().unwrap();
+        if record.generation == generation {
+            record.orders.insert(key, r.clone());
+        }
+        r
+    });
+    r.iter().map(|i| goals[*i].clone()).collect()
+}
+
+/// The positions of the goals in the order `plan` gives,
+/// with the goals on the `recursive` relations sensitive to the order.
+fn order(goals: &[Goal], bound: &HashSet<Handle<Symbol>>, recursive: &HashSet<Key>, db: &Database) -> Vec<usize> {
+    let vars: Vec<HashSet<_>> = goals
+        .iter()
+        .map(|goal| {
+            let mut r = HashSet::new();
+            goal_variables(goal, &mut r);
+            r
+        })
+        .collect();
+    let relations = sensitive(db);
+    let sensitive: Vec<bool> = goals
+        .iter()
+        .map(|goal| {
+            is_sensitive(goal, &relations)
+                || matches!(goal, Goal::Fact(query) if recursive.contains(&(query.name.clone(), query.args.len())))
+        })
+        .collect();
+    // the variables bound before each goal in the written order
+    let mut before = vec![];
+    let mut r = bound.clone();
+    for goal in goals {
+        before.push(r.clone());
+        bind_goal(goal, &mut r);
+    }
+
+    let mut bound = bound.clone();
+    let mut pending: Vec<usize> = (0..goals.len()).collect();
+    let mut r = vec![];
+    while !pending.is_empty() {
+        let allowed = |i: usize| {
+            if sensitive[i] && vars[i].iter().any(|k| bound.contains(k) != before[i].contains(k)) {
+                return false;
+            }
+            // must not bind a variable a sensitive goal left has unbound
+            let mut next = bound.clone();
+            bind_goal(&goals[i], &mut next);
+            pending.iter().all(|j| {
+                *j == i || !sensitive[*j] || vars[*j].iter().all(|k| !next.contains(k) || before[*j].contains(k))
+            })
+        };
+        let pick = pending
+            .iter()
+            .copied()
+            .filter(|i| allowed(*i))
+            .min_by_key(|i| match &goals[*i] {
+                Goal::Fact(query) if !sensitive[*i] => (1 + estimate(query, &bound, db), *i),
+                _ => (0, *i),
+            })
+            .unwrap_or(pending[0]);
+        pending.retain(|x| *x != pick);
+        bind_goal(&goals[pick], &mut bound);
+        r.push(pick);
+    }
+    r
+}
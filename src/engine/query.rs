@@ -1,227 +1,652 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    iter::{empty, once},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use super::environment::Database;
+use super::{
+    aggregate::aggregate, builtin::BUILTINS, environment::Database, error::Error, eval::eval_calls, join::join_goals,
+    planner::{plan, plan_rule},
+    stratify::Key,
+    tabling::solve_tabled,
+};
 
 use crate::structs::{
-    fact::{ValueLine, ValueTable},
-    rule::{Expr, FactQuery, Pattern, RuleBody, RuleTable},
+    fact::ValueLine,
+    foreign::ForeignRelation,
+    plan::RuleId,
+    rule::{Aggregate, Call, Expr, FactQuery, Goal, Pattern, RuleBody, RuleTable},
     scope::{Scope, SimpleScope},
     value::{Handle, Value},
 };
 
-// use rayon::prelude::*;
 use sexpr_ir::gast::symbol::Symbol;
 
-fn unify(pattern: &Expr, value: &Value, env: &Handle<Scope>) -> Result<(), ()> {
-    match pattern {
-        Expr::Value(v) => {
-            if v == value {
-                Ok(())
-            } else {
-                Err(())
-            }
+static FRESH_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Variable bindings of a solution. Variables are bound to terms,
+/// which may contain other variables.
+pub type Bindings = Handle<Scope<Expr>>;
+
+/// Lazily produced solutions, one scope per solution,
+/// or the error which stopped the evaluation.
+pub type Solutions = Box<dyn Iterator<Item = Result<Bindings, Error>>>;
+
+/// Solves `f` for each of the solutions, errors are passed on.
+pub(crate) fn and_then<F>(this: Solutions, mut f: F) -> Solutions
+where
+    F: FnMut(Bindings) -> Solutions + 'static,
+{
+    Box::new(this.flat_map(move |x| -> Solutions {
+        match x {
+            Ok(scope) => f(scope),
+            Err(e) => Box::new(once(Err(e))),
+        }
+    }))
+}
+
+/// Follows the bindings of a variable until an unbound variable or another term.
+pub fn walk(this: &Expr, env: &Bindings) -> Expr {
+    let mut r = this.clone();
+    while let Expr::Variable(k) = &r {
+        if let Some(x) = env.find(k) {
+            r = x;
+        } else {
+            break;
         }
+    }
+    r
+}
+
+fn resolve_inner(this: &Expr, env: &Bindings, visiting: &mut Vec<Handle<Symbol>>) -> Expr {
+    match this {
+        Expr::Value(_) => this.clone(),
         Expr::Variable(k) => {
-            if let Some(ref v) = env.find(k) {
-                if v == value {
-                    Ok(())
-                } else {
-                    Err(())
-                }
+            // a cyclic term, left from unifying without occurs check
+            if visiting.contains(k) {
+                return this.clone();
+            }
+            if let Some(x) = env.find(k) {
+                visiting.push(k.clone());
+                let r = resolve_inner(&x, env, visiting);
+                visiting.pop();
+                r
             } else {
-                env.set(k, value);
-                Ok(())
+                this.clone()
             }
         }
-        Expr::FunctionCall(_) => Err(()),
+        Expr::Pair(p) => Expr::Pair(Handle::new((
+            resolve_inner(&p.0, env, visiting),
+            resolve_inner(&p.1, env, visiting),
+        ))),
+        Expr::Tuple(t) => Expr::Tuple(t.iter().map(|x| resolve_inner(x, env, visiting)).collect()),
+        Expr::Dict(entries, rest) => Expr::Dict(
+            entries
+                .iter()
+                .map(|(k, x)| (k.clone(), resolve_inner(x, env, visiting)))
+                .collect(),
+            rest.as_ref().map(|x| Handle::new(resolve_inner(x, env, visiting))),
+        ),
+        Expr::FunctionCall(c) => Expr::FunctionCall(Handle::new(Call {
+            call_name: c.call_name.clone(),
+            args: c.args.iter().map(|x| resolve_inner(x, env, visiting)).collect(),
+        })),
+    }
+}
+
+/// Substitutes the bound variables in the term, at any depth.
+pub fn resolve(this: &Expr, env: &Bindings) -> Expr {
+    resolve_inner(this, env, &mut vec![])
+}
+
+fn occurs(k: &Handle<Symbol>, this: &Expr, env: &Bindings) -> bool {
+    match walk(this, env) {
+        Expr::Variable(x) => &x == k,
+        Expr::Pair(p) => occurs(k, &p.0, env) || occurs(k, &p.1, env),
+        Expr::Tuple(t) => t.iter().any(|x| occurs(k, x, env)),
+        Expr::Dict(entries, rest) => {
+            entries.iter().any(|(_, x)| occurs(k, x, env)) || rest.iter().any(|x| occurs(k, x, env))
+        }
+        Expr::FunctionCall(c) => c.args.iter().any(|x| occurs(k, x, env)),
+        Expr::Value(_) => false,
     }
 }
 
-fn matching(
-    pattern: &Pattern,
-    value: &Expr,
-    record: &mut HashMap<Handle<Symbol>, Expr>,
+fn unify_all<'a>(
+    left: impl Iterator<Item = &'a Expr>,
+    right: impl Iterator<Item = Expr>,
+    env: &Bindings,
+    occurs_check: bool,
 ) -> Result<(), ()> {
-    match pattern {
-        Pattern::Ignore => Ok(()),
-        Pattern::Variable(k) => {
-            if let Some(c) = record.get(k) {
-                if c != value {
-                    return Err(());
-                }
-            } else {
-                record.insert(k.clone(), value.clone());
+    left.zip(right)
+        .try_for_each(|(l, r)| unify(l, &r, env, occurs_check))
+}
+
+fn dict_expr(entries: Vec<(Handle<String>, Expr)>, rest: Option<&Expr>) -> Expr {
+    match rest {
+        Some(rest) if entries.is_empty() => rest.clone(),
+        _ => Expr::Dict(entries.into(), rest.cloned().map(Handle::new)),
+    }
+}
+
+/// Unifies the values of the keys in both dicts, the keys only in one
+/// of them go to the rest of the other, which must then have one.
+fn unify_dict(
+    (a, rest_a): (&[(Handle<String>, Expr)], Option<&Expr>),
+    (b, rest_b): (&[(Handle<String>, Expr)], Option<&Expr>),
+    env: &Bindings,
+    occurs_check: bool,
+) -> Result<(), ()> {
+    for (k, x) in a {
+        if let Some((_, y)) = b.iter().find(|(j, _)| j == k) {
+            unify(x, y, env, occurs_check)?;
+        }
+    }
+    let only = |x: &[(Handle<String>, Expr)], y: &[(Handle<String>, Expr)]| -> Vec<_> {
+        x.iter().filter(|(k, _)| !y.iter().any(|(j, _)| j == k)).cloned().collect()
+    };
+    let (only_a, only_b) = (only(a, b), only(b, a));
+    match (rest_a, rest_b) {
+        (None, None) if only_a.is_empty() && only_b.is_empty() => Ok(()),
+        (Some(r), None) if only_a.is_empty() => unify(r, &dict_expr(only_b, None), env, occurs_check),
+        (None, Some(r)) if only_b.is_empty() => unify(r, &dict_expr(only_a, None), env, occurs_check),
+        (Some(r), Some(s)) if only_a.is_empty() => unify(r, &dict_expr(only_b, Some(s)), env, occurs_check),
+        (Some(r), Some(s)) if only_b.is_empty() => unify(s, &dict_expr(only_a, Some(r)), env, occurs_check),
+        (Some(r), Some(s)) => {
+            // the keys in neither dict
+            let t = Expr::Variable(fresh_variable(&Handle::new(Symbol::new("_"))));
+            unify(r, &dict_expr(only_b, Some(&t)), env, occurs_check)?;
+            unify(s, &dict_expr(only_a, Some(&t)), env, occurs_check)
+        }
+        _ => Err(()),
+    }
+}
+
+/// Structural unification of two terms.
+/// New bindings are written to the top level of `env`.
+pub(crate) fn unify(left: &Expr, right: &Expr, env: &Bindings, occurs_check: bool) -> Result<(), ()> {
+    let left = walk(left, env);
+    let right = walk(right, env);
+    match (&left, &right) {
+        (Expr::Variable(x), Expr::Variable(y)) if x == y => Ok(()),
+        (Expr::Variable(k), t) | (t, Expr::Variable(k)) => {
+            if occurs_check && occurs(k, t, env) {
+                return Err(());
             }
+            env.set(k, t);
             Ok(())
         }
-        Pattern::Constant(c) => {
-            if let Expr::Value(value) = value {
-                if c == value {
-                    Ok(())
-                } else {
-                    Err(())
-                }
+        (Expr::Value(x), Expr::Value(y)) => {
+            if x == y {
+                Ok(())
             } else {
                 Err(())
             }
         }
-        Pattern::Tuple(_) => todo!(),
-        Pattern::List(_, _) => todo!(),
+        (Expr::Pair(p), Expr::Pair(q)) => {
+            unify(&p.0, &q.0, env, occurs_check)?;
+            unify(&p.1, &q.1, env, occurs_check)
+        }
+        (Expr::Pair(p), Expr::Value(Value::Pair(v))) | (Expr::Value(Value::Pair(v)), Expr::Pair(p)) => {
+            unify(&p.0, &Expr::Value(v.0.clone()), env, occurs_check)?;
+            unify(&p.1, &Expr::Value(v.1.clone()), env, occurs_check)
+        }
+        (Expr::Tuple(a), Expr::Tuple(b)) => {
+            if a.len() != b.len() {
+                return Err(());
+            }
+            unify_all(a.iter(), b.iter().cloned(), env, occurs_check)
+        }
+        (Expr::Tuple(a), Expr::Value(Value::Tuple(b))) | (Expr::Value(Value::Tuple(b)), Expr::Tuple(a)) => {
+            if a.len() != b.0.len() {
+                return Err(());
+            }
+            unify_all(a.iter(), b.0.iter().cloned().map(Expr::Value), env, occurs_check)
+        }
+        (Expr::Dict(a, rest_a), Expr::Dict(b, rest_b)) => {
+            unify_dict((a, rest_a.as_deref()), (b, rest_b.as_deref()), env, occurs_check)
+        }
+        (Expr::Dict(a, rest), Expr::Value(Value::Dict(d))) | (Expr::Value(Value::Dict(d)), Expr::Dict(a, rest)) => {
+            let b: Vec<_> = d.0.iter().map(|(k, v)| (k.clone(), Expr::Value(v.clone()))).collect();
+            unify_dict((a, rest.as_deref()), (&b, None), env, occurs_check)
+        }
+        _ => Err(()),
     }
 }
 
-fn swap_result<T, E>(i: Result<T, E>) -> Result<E, T> {
-    match i {
-        Ok(x) => Err(x),
-        Err(x) => Ok(x),
+pub(crate) fn fresh_variable(k: &Handle<Symbol>) -> Handle<Symbol> {
+    let n = FRESH_COUNTER.fetch_add(1, Ordering::Relaxed);
+    Handle::new(Symbol::new(&format!("{}#{}", k, n)))
+}
+
+fn rename_variable(k: &Handle<Symbol>, record: &mut HashMap<Handle<Symbol>, Handle<Symbol>>) -> Expr {
+    if k.0.as_str() == "_" {
+        return Expr::Variable(fresh_variable(k));
     }
+    let r = record.entry(k.clone()).or_insert_with(|| fresh_variable(k));
+    Expr::Variable(r.clone())
+}
+
+fn rename_expr(this: &Expr, record: &mut HashMap<Handle<Symbol>, Handle<Symbol>>) -> Expr {
+    match this {
+        Expr::Value(_) => this.clone(),
+        Expr::Variable(k) => rename_variable(k, record),
+        Expr::FunctionCall(c) => {
+            let args = c.args.iter().map(|x| rename_expr(x, record)).collect();
+            Expr::FunctionCall(Handle::new(Call {
+                call_name: c.call_name.clone(),
+                args,
+            }))
+        }
+        Expr::Pair(p) => Expr::Pair(Handle::new((rename_expr(&p.0, record), rename_expr(&p.1, record)))),
+        Expr::Tuple(t) => Expr::Tuple(t.iter().map(|x| rename_expr(x, record)).collect()),
+        Expr::Dict(entries, rest) => Expr::Dict(
+            entries.iter().map(|(k, x)| (k.clone(), rename_expr(x, record))).collect(),
+            rest.as_ref().map(|x| Handle::new(rename_expr(x, record))),
+        ),
+    }
+}
+
+/// Turns a rule head pattern into a term with renamed variables.
+fn rename_pattern(this: &Pattern, record: &mut HashMap<Handle<Symbol>, Handle<Symbol>>) -> Expr {
+    match this {
+        Pattern::Ignore => rename_variable(&Handle::new(Symbol::new("_")), record),
+        Pattern::Variable(k) => rename_variable(k, record),
+        Pattern::Constant(c) => Expr::Value(c.clone()),
+        Pattern::Tuple(patterns) => Expr::Tuple(patterns.iter().map(|x| rename_pattern(x, record)).collect()),
+        Pattern::List(patterns, extend) => {
+            let items: Vec<_> = patterns.iter().map(|x| rename_pattern(x, record)).collect();
+            let tail = extend
+                .as_ref()
+                .map_or(Expr::Value(Value::Nil), |x| rename_pattern(x, record));
+            items
+                .into_iter()
+                .rev()
+                .fold(tail, |tail, item| Expr::Pair(Handle::new((item, tail))))
+        }
+        Pattern::Dict(patterns, rest) => Expr::Dict(
+            patterns.iter().map(|(k, x)| (k.clone(), rename_pattern(x, record))).collect(),
+            rest.as_ref().map(|x| Handle::new(rename_pattern(x, record))),
+        ),
+    }
+}
+
+fn rename_goal(this: &Goal, record: &mut HashMap<Handle<Symbol>, Handle<Symbol>>) -> Goal {
+    match this {
+        Goal::Fact(query) => Goal::Fact(FactQuery {
+            name: query.name.clone(),
+            args: query.args.iter().map(|x| rename_expr(x, record)).collect(),
+        }),
+        Goal::Not(goal) => Goal::Not(Handle::new(rename_goal(goal, record))),
+        Goal::Or(goals) => Goal::Or(rename(goals, record)),
+        Goal::Aggregate(a) => {
+            let group = a
+                .group
+                .iter()
+                .filter_map(|k| match rename_variable(k, record) {
+                    Expr::Variable(k) => Some(k),
+                    _ => None,
+                })
+                .collect();
+            Goal::Aggregate(Handle::new(Aggregate {
+                op: a.op,
+                result: rename_expr(&a.result, record),
+                value: a.value.as_ref().map(|x| rename_expr(x, record)),
+                goal: rename_goal(&a.goal, record),
+                group,
+            }))
+        }
+    }
+}
+
+/// Renames the variables of the goals to fresh names, so each call
+/// of a rule has its own. `record` keeps the names given so far.
+pub fn rename(this: &[Goal], record: &mut HashMap<Handle<Symbol>, Handle<Symbol>>) -> Handle<[Goal]> {
+    this.iter().map(|goal| rename_goal(goal, record)).collect()
 }
 
-fn query_value_line(this: &ValueLine, env: &Handle<Scope>, prarms: &[Expr]) -> Result<Handle<Scope>, ()> {
+fn query_value_line(this: &ValueLine, env: &Bindings, prarms: &[Expr]) -> Result<Bindings, ()> {
     // if err return err
     let env = env.new_level(SimpleScope::new());
     for (value, pattern) in this.0.iter().zip(prarms.iter()) {
-        if unify(pattern, value, &env).is_err() {
+        if unify(pattern, &Expr::Value(value.clone()), &env, false).is_err() {
             return Err(());
         }
     }
     Ok(env)
 }
 
-fn query_rule_body(
+/// Solves a rule for the args. `id` names the rule in the database,
+/// to reuse the plans of its body.
+pub(crate) fn query_rule_body(
     this: &RuleBody,
+    id: Option<RuleId>,
     env: &Handle<Database>,
-    scope: &Handle<Scope>,
+    scope: &Bindings,
     prarms: &[Expr],
-) -> Result<Handle<Scope>, ()> {
+) -> Solutions {
     let new_scope = scope.new_level(SimpleScope::new());
 
-    let mut capture = HashMap::new();
-    this.prarms
+    let mut record = HashMap::new();
+    let r = this
+        .prarms
         .iter()
+        .map(|pattern| rename_pattern(pattern, &mut record))
         .zip(prarms.iter())
-        .try_for_each(|(pattern, value)| matching(pattern, value, &mut capture))?;
+        .try_for_each(|(pattern, value)| unify(&pattern, value, &new_scope, env.occurs_check));
+    if r.is_err() {
+        return Box::new(empty());
+    }
 
-    for query in this.bodys.iter() {
-        if query_fact(query, env, &new_scope, &mut capture).is_none() {
-            return Err(());
+    let bodys = rename(&this.bodys, &mut record);
+    let bodys = if env.fixed_goal_order {
+        bodys
+    } else {
+        // the head variables bound by the call, by their names in the rule and here
+        let head: Vec<_> = record
+            .iter()
+            .filter(|(_, k)| resolve(&Expr::Variable((*k).clone()), &new_scope).to_value().is_some())
+            .collect();
+        let bound = head.iter().map(|(_, k)| (*k).clone()).collect();
+        match id {
+            Some(id) => {
+                let mut head: Vec<_> = head.iter().map(|(x, _)| (*x).clone()).collect();
+                head.sort_by(|a, b| a.0.cmp(&b.0));
+                plan_rule(id, head, &bodys, &bound, env).into()
+            }
+            None => plan(&bodys, &bound, env).into(),
         }
+    };
+    query_goals(&bodys, env, &new_scope)
+}
+
+/// Solves the goals one after another, backtracking into the goals
+/// before for each of their solutions.
+pub fn query_goals(this: &[Goal], env: &Handle<Database>, scope: &Bindings) -> Solutions {
+    if env.hash_join {
+        return join_goals(this, env, scope);
     }
-    
-    Ok(new_scope)
+    let init: Solutions = Box::new(once(Ok(scope.clone())));
+    this.iter().fold(init, |scopes, goal| {
+        let goal = goal.clone();
+        let env = env.clone();
+        and_then(scopes, move |scope| query_goal(&goal, &env, &scope))
+    })
 }
 
-pub fn query_value_table(
-    this: &ValueTable,
-    env: &Handle<Scope>,
-    prarms: &[Expr],
-) -> Option<Handle<Scope>> {
-    let r: Result<Vec<_>, ()> = this
-        .0
-        .par_iter()
-        .map(|values| swap_result(query_value_line(values, &env.new_level(SimpleScope::new()), prarms)))
-        .collect();
-    // if err return ok
-    if r.is_err() {
-        Some(new_env)
-    } else {
-        // traceback
-        None
+pub fn query_goal(this: &Goal, env: &Handle<Database>, scope: &Bindings) -> Solutions {
+    match this {
+        Goal::Fact(query) => query_fact(query, env, scope),
+        // negation as failure, stops at the first solution of the inner goal
+        Goal::Not(goal) => match query_goal(goal, env, scope).next() {
+            None => Box::new(once(Ok(scope.clone()))),
+            Some(Ok(_)) => Box::new(empty()),
+            Some(Err(e)) => Box::new(once(Err(e))),
+        },
+        // the branches share the bindings made before, each adds its own
+        Goal::Or(goals) => {
+            let env = env.clone();
+            let scope = scope.clone();
+            let goals = goals.clone();
+            Box::new((0..goals.len()).flat_map(move |i| query_goal(&goals[i], &env, &scope)))
+        }
+        Goal::Aggregate(a) => query_aggregate(a, env, scope),
     }
 }
 
-pub fn query_rule_table(
-    this: &RuleTable,
-    env: &Handle<Database>,
-    scope: &Handle<Scope>,
-    prarms: &[Expr],
-) -> Option<Handle<Scope>> {
-    let r: Result<_, _> = this
-        .0
-        .par_iter()
-        .try_for_each(|value| swap_result(query_rule_body(value, env, &scope.new_level(SimpleScope::new()), &prarms)));
-    // if err return ok
-    if let Err(r) = r {
-        Some(r)
-    } else {
-        // traceback
-        None
+/// Solves the inner goal to the end and gives one solution per group,
+/// binding the group variables and the result.
+/// With the group bound before, a group without solutions still counts.
+/// An error in the goal or in aggregating a group is the only result.
+fn query_aggregate(this: &Aggregate, env: &Handle<Database>, scope: &Bindings) -> Solutions {
+    let key_of = |s: &Bindings| -> Vec<Expr> {
+        this.group
+            .iter()
+            .map(|k| resolve(&Expr::Variable(k.clone()), s))
+            .collect()
+    };
+    // the groups in the order they are first seen, and the position of each
+    let mut groups: Vec<(Vec<Expr>, Vec<Value>)> = vec![];
+    let mut positions: HashMap<Vec<Expr>, usize> = HashMap::new();
+    for s in query_goal(&this.goal, env, scope) {
+        let s = match s {
+            Ok(s) => s,
+            Err(e) => return Box::new(once(Err(e))),
+        };
+        let item = match &this.value {
+            Some(x) => match resolve(x, &s).to_value() {
+                Some(x) => x,
+                // an unbound aggregated term has no value
+                None => return Box::new(empty()),
+            },
+            None => Value::Nil,
+        };
+        let key = key_of(&s);
+        match positions.get(&key) {
+            Some(i) => groups[*i].1.push(item),
+            None => {
+                positions.insert(key.clone(), groups.len());
+                groups.push((key, vec![item]));
+            }
+        }
     }
+    if groups.is_empty() {
+        let key = key_of(scope);
+        if key.iter().all(|x| x.to_value().is_some()) {
+            groups.push((key, vec![]));
+        }
+    }
+    let mut r = vec![];
+    for (key, items) in groups {
+        let value = match aggregate(this.op, &items) {
+            Ok(Some(x)) => x,
+            Ok(None) => continue,
+            Err(e) => return Box::new(once(Err(e))),
+        };
+        let new_scope = scope.new_level(SimpleScope::new());
+        let bound = this
+            .group
+            .iter()
+            .zip(key.iter())
+            .try_for_each(|(k, x)| unify(&Expr::Variable(k.clone()), x, &new_scope, false))
+            .and_then(|_| unify(&this.result, &Expr::Value(value), &new_scope, false));
+        if bound.is_ok() {
+            r.push(Ok(new_scope));
+        }
+    }
+    Box::new(r.into_iter())
 }
 
-pub fn query_fact(
-    this: &FactQuery,
+/// Unifies the lines of the table with `prarms`, one line per step.
+/// Only the lines an index gives for the bound args are tried, if any applies.
+/// The table lock is only held while a line is being fetched, lines
+/// removed meanwhile are skipped, lines added meanwhile may be given.
+fn query_value_table(
     env: &Handle<Database>,
-    scope: &Handle<Scope>,
-    prarms: &mut HashMap<Handle<Symbol>, Expr>,
-) -> Option<()> {
-    let prarms: Option<Vec<_>> = this.args.par_iter().map(|x| binding(x, prarms)).collect();
-    let prarms = prarms?;
-    let k = (this.name.clone(), prarms.len());
-    let record = env.rules.read().unwrap();
-    if let Some(rules) = record.0.get(&k) {
-        return if let Some(x) = query_rule_table(rules, env, scope, &prarms) {
-            x.flatten().0
-                .read()
-                .unwrap()
-                .par_iter()
-                .try_for_each(|(k, v)| {
-                    if let Some(ref x) = scope.find(k) {
-                        if x == v {
-                            Some(())
-                        } else {
-                            None
-                        }
-                    } else {
-                        scope.set(k, v);
-                        Some(())
-                    }
-                })
-        } else {
-            None
-        };
+    key: (Handle<Symbol>, usize),
+    scope: &Bindings,
+    prarms: Handle<[Expr]>,
+) -> Solutions {
+    let bound: Vec<_> = prarms.iter().map(|x| resolve(x, scope).to_value()).collect();
+    let lines = env.facts.read().unwrap().0.get(&key).and_then(|x| x.lookup(&bound));
+    let env = env.clone();
+    let scope = scope.clone();
+    // `None` past the last line, `Some(None)` for a removed line
+    let fetch = move |i: usize| {
+        let record = env.facts.read().unwrap();
+        record.0.get(&key)?.lines.get(i).cloned()
+    };
+    let r: Box<dyn Iterator<Item = ValueLine>> = match lines {
+        Some(lines) => Box::new(lines.into_iter().filter_map(fetch).flatten()),
+        None => Box::new((0..).map_while(fetch).flatten()),
+    };
+    Box::new(r.filter_map(move |values| query_value_line(&values, &scope, &prarms).ok().map(Ok)))
+}
+
+fn query_foreign(this: &ForeignRelation, scope: &Bindings, prarms: Handle<[Expr]>) -> Solutions {
+    let bound: Vec<_> = prarms.iter().map(|x| resolve(x, scope).to_value()).collect();
+    let scope = scope.clone();
+    let arity = this.arity;
+    let r = (this.body)(&bound)
+        .filter(move |values| values.len() == arity)
+        .filter_map(move |values| query_value_line(&ValueLine(values.into()), &scope, &prarms).ok())
+        .map(Ok);
+    Box::new(r)
+}
+
+/// Solves the rules one after another. Unless the relation is a bag,
+/// a solution binding the args to values given before is skipped.
+fn query_rule_table(
+    this: RuleTable,
+    k: Key,
+    env: &Handle<Database>,
+    scope: &Bindings,
+    prarms: Handle<[Expr]>,
+    bag: bool,
+) -> Solutions {
+    let env = env.clone();
+    let scope = scope.clone();
+    let args = prarms.clone();
+    let r = this
+        .0
+        .into_iter()
+        .enumerate()
+        .flat_map(move |(i, value)| query_rule_body(&value, Some((k.clone(), i)), &env, &scope, &prarms));
+    if bag {
+        return Box::new(r);
     }
-    let record = env.facts.read().unwrap();
-    if let Some(facts) = record.0.get(&k) {
-        return if let Some(x) = query_value_table(facts, scope, &prarms) {
-            x.flatten()
-                .0
-                .read()
-                .unwrap()
-                .par_iter()
-                .try_for_each(|(k, v)| {
-                    if let Some(ref x) = scope.find(k) {
-                        if x == v {
-                            Some(())
-                        } else {
-                            None
-                        }
-                    } else {
-                        scope.set(k, v);
-                        Some(())
-                    }
-                })
-        } else {
-            None
+    let mut seen = HashSet::new();
+    Box::new(r.filter(move |s| {
+        let s = match s {
+            Ok(s) => s,
+            Err(_) => return true,
         };
+        let values: Option<Vec<_>> = args.iter().map(|x| resolve(x, s).to_value()).collect();
+        match values {
+            Some(x) => seen.insert(x),
+            None => true,
+        }
+    }))
+}
+
+fn test_builtin(name: &Handle<Symbol>, args: &[Value]) -> Result<bool, Error> {
+    match BUILTINS.get(name) {
+        Some(f) => Ok(matches!(f.call(args)?, Value::Bool(true))),
+        None => Ok(false),
     }
-    None
 }
 
-fn binding(x: &Expr, prarms: &RwLock<HashMap<Handle<Symbol>, Expr>>) -> Option<Expr> {
-    match x {
-        Expr::Variable(k) => {
-            if let Some(x) = prarms.read().unwrap().get(k) {
-                Some(x.clone())
-            } else {
-                prarms
-                    .write()
-                    .unwrap()
-                    .get(k)
-                    .cloned()
-                    // .map_or_else(|| None, |x| Some(x.clone()))
-            }
+/// Whether the goal is a comparison rather than a relation.
+pub(crate) fn is_builtin_goal(this: &FactQuery) -> bool {
+    this.args.len() == 2 && matches!(this.name.0.as_str(), "=" | "!=" | "<" | "<=" | ">" | ">=")
+}
+
+/// Comparison goals, tested on the current bindings instead of a relation.
+/// `=` binds the unbound variables of one side to the other side,
+/// ordering comparisons have no solution while an argument is unbound.
+/// Values a comparison does not apply to, as a number and a string, are an error.
+fn query_builtin(this: &FactQuery, prarms: &[Expr], env: &Handle<Database>, scope: &Bindings) -> Option<Solutions> {
+    if !is_builtin_goal(this) {
+        return None;
+    }
+    let name = this.name.0.as_str();
+    let left = resolve(&prarms[0], scope);
+    let right = resolve(&prarms[1], scope);
+    let r = match (name, left.to_value().zip(right.to_value())) {
+        (_, Some((a, b))) => match test_builtin(&this.name, &[a, b]) {
+            Ok(r) => Some(scope.clone()).filter(|_| r),
+            Err(e) => return Some(Box::new(once(Err(e)))),
+        },
+        ("=", None) => {
+            let new_scope = scope.new_level(SimpleScope::new());
+            unify(&left, &right, &new_scope, env.occurs_check)
+                .ok()
+                .map(|_| new_scope)
+        }
+        ("!=", None) => {
+            let new_scope = scope.new_level(SimpleScope::new());
+            unify(&left, &right, &new_scope, env.occurs_check)
+                .err()
+                .map(|_| scope.clone())
         }
-        _ => Some(x.clone()),
+        _ => None,
+    };
+    Some(Box::new(r.into_iter().map(Ok)))
+}
+
+/// Returns the solutions lazily, each a new level over `scope`
+/// holding the variables bound by this query.
+pub fn query_fact(this: &FactQuery, env: &Handle<Database>, scope: &Bindings) -> Solutions {
+    let prarms = match eval_args(&this.args, env, scope) {
+        Ok(prarms) => prarms,
+        // a call on a variable not bound yet has no solution
+        Err(Error::UnboundVariable(_)) => return Box::new(empty()),
+        Err(e) => return Box::new(once(Err(e))),
+    };
+    if let Some(r) = query_builtin(this, &prarms, env, scope) {
+        return r;
+    }
+    let k = (this.name.clone(), prarms.len());
+    if env.tabled.read().unwrap().0.contains(&k) {
+        return query_tabled(k, env, scope, prarms);
+    }
+    query_relation(k, env, scope, prarms)
+}
+
+/// Solves a relation by its rules, its facts or its native callback,
+/// the first of them it has.
+pub(crate) fn query_relation(
+    k: (Handle<Symbol>, usize),
+    env: &Handle<Database>,
+    scope: &Bindings,
+    prarms: Handle<[Expr]>,
+) -> Solutions {
+    let rules = env.rules.read().unwrap().0.get(&k).cloned();
+    if let Some(rules) = rules {
+        let bag = env.bags.read().unwrap().0.contains(&k);
+        return query_rule_table(rules, k, env, scope, prarms, bag);
     }
+    if env.facts.read().unwrap().0.contains_key(&k) {
+        return query_value_table(env, k, scope, prarms);
+    }
+    let foreign = env.foreigns.read().unwrap().0.get(&k).cloned();
+    if let Some(foreign) = foreign {
+        return query_foreign(&foreign, scope, prarms);
+    }
+    Box::new(empty())
+}
+
+/// Unifies `prarms` with the answers of the tabled call, instead of solving it again.
+fn query_tabled(
+    k: (Handle<Symbol>, usize),
+    env: &Handle<Database>,
+    scope: &Bindings,
+    prarms: Handle<[Expr]>,
+) -> Solutions {
+    let args: Handle<[Expr]> = prarms.iter().map(|x| resolve(x, scope)).collect();
+    let answers = match solve_tabled(&k, &args, env) {
+        Ok(x) => x,
+        Err(e) => return Box::new(once(Err(e))),
+    };
+    let scope = scope.clone();
+    let occurs_check = env.occurs_check;
+    let r = answers.into_iter().filter_map(move |answer| {
+        let mut record = HashMap::new();
+        let answer = answer.iter().map(|x| rename_expr(x, &mut record));
+        let new_scope = scope.new_level(SimpleScope::new());
+        unify_all(prarms.iter(), answer, &new_scope, occurs_check)
+            .ok()
+            .map(|_| Ok(new_scope))
+    });
+    Box::new(r)
+}
+
+/// Evaluates the function calls in the args with the current bindings.
+fn eval_args(this: &[Expr], db: &Database, env: &Bindings) -> Result<Handle<[Expr]>, Error> {
+    this.iter()
+        .map(|x| match x {
+            Expr::Value(_) | Expr::Variable(_) => Ok(x.clone()),
+            _ => eval_calls(&resolve(x, env), db, &Scope::new()),
+        })
+        .collect()
 }
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{
+    rule::{Goal, RuleRecord},
+    value::Handle,
+};
+
+use super::error::Error;
+
+pub type Key = (Handle<Symbol>, usize);
+
+/// From a relation to the relations in its bodys, `true` when through a negation or an aggregate.
+pub type Graph = HashMap<Key, Vec<(Key, bool)>>;
+
+/// Collects the relations a goal depends on, `true` when through
+/// a negation or an aggregate, which need the relation to be complete.
+pub(crate) fn goal_dependencies(this: &Goal, negative: bool, record: &mut Vec<(Key, bool)>) {
+    match this {
+        Goal::Fact(q) => record.push(((q.name.clone(), q.args.len()), negative)),
+        Goal::Not(g) => goal_dependencies(g, true, record),
+        Goal::Or(gs) => gs.iter().for_each(|g| goal_dependencies(g, negative, record)),
+        Goal::Aggregate(a) => goal_dependencies(&a.goal, true, record),
+    }
+}
+
+/// The relations in the bodys of the rules of a relation.
+fn relation_dependencies(rules: &RuleRecord, k: &Key) -> Vec<(Key, bool)> {
+    let mut record = vec![];
+    rules
+        .0
+        .get(k)
+        .iter()
+        .flat_map(|table| table.0.iter())
+        .flat_map(|body| body.bodys.iter())
+        .for_each(|goal| goal_dependencies(goal, false, &mut record));
+    record
+}
+
+/// The dependency graph of the rules, from a relation to the relations in its bodys.
+pub fn dependencies(rules: &RuleRecord) -> Graph {
+    rules
+        .0
+        .keys()
+        .map(|k| (k.clone(), relation_dependencies(rules, k)))
+        .collect()
+}
+
+/// The dependency graph of the relations `k` depends on,
+/// and the strongly connected component of `k` in it.
+fn component(rules: &RuleRecord, k: &Key) -> (Graph, HashSet<Key>) {
+    let mut graph = Graph::new();
+    let mut stack = vec![k.clone()];
+    while let Some(x) = stack.pop() {
+        if graph.contains_key(&x) {
+            continue;
+        }
+        let deps = relation_dependencies(rules, &x);
+        stack.extend(deps.iter().map(|(dep, _)| dep.clone()));
+        graph.insert(x, deps);
+    }
+    // the component is what depends on `k` among them
+    let mut reverse: HashMap<&Key, Vec<&Key>> = HashMap::new();
+    for (x, deps) in graph.iter() {
+        deps.iter().for_each(|(dep, _)| reverse.entry(dep).or_default().push(x));
+    }
+    let mut component = HashSet::new();
+    let mut stack = vec![k];
+    while let Some(x) = stack.pop() {
+        if component.insert(x.clone()) {
+            stack.extend(reverse.get(x).into_iter().flatten().copied());
+        }
+    }
+    (graph, component)
+}
+
+/// The relations recursive with `k`, those it depends on which depend on it,
+/// and `k` itself.
+pub(crate) fn recursive_relations(rules: &RuleRecord, k: &Key) -> HashSet<Key> {
+    component(rules, k).1
+}
+
+/// Checks that no relation depends on itself through a negation or an
+/// aggregate in the strongly connected component of `k`. After adding
+/// rules of `k` to a stratifiable program, that is enough for it to stay so.
+/// Only the relations `k` depends on are visited.
+pub fn check_stratified(rules: &RuleRecord, k: &Key) -> Result<(), Error> {
+    let (graph, component) = component(rules, k);
+    let negative = component
+        .iter()
+        .any(|x| graph[x].iter().any(|(dep, negative)| *negative && component.contains(dep)));
+    if negative {
+        return Err(Error::Unstratifiable {
+            name: k.0.clone(),
+            arity: k.1,
+        });
+    }
+    Ok(())
+}
+
+/// Assigns each relation a stratum, so that a relation is in a stratum
+/// no lower than the relations it depends on, and higher than the ones
+/// it depends on through a negation or an aggregate.
+/// Relations without rules are in stratum 0.
+pub fn stratify(rules: &RuleRecord) -> Result<HashMap<Key, usize>, Error> {
+    let graph = dependencies(rules);
+    let mut strata: HashMap<Key, usize> = HashMap::new();
+    for (k, deps) in graph.iter() {
+        strata.entry(k.clone()).or_insert(0);
+        for (dep, _) in deps {
+            strata.entry(dep.clone()).or_insert(0);
+        }
+    }
+    // a stratum above the number of relations can only come from a negative cycle
+    let limit = strata.len();
+    loop {
+        let mut changed = false;
+        for (k, deps) in graph.iter() {
+            let r = deps
+                .iter()
+                .map(|(dep, negative)| strata[dep] + *negative as usize)
+                .max()
+                .unwrap_or(0);
+            if r > strata[k] {
+                if r > limit {
+                    return Err(Error::Unstratifiable {
+                        name: k.0.clone(),
+                        arity: k.1,
+                    });
+                }
+                strata.insert(k.clone(), r);
+                changed = true;
+            }
+        }
+        if !changed {
+            return Ok(strata);
+        }
+    }
+}
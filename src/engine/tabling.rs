@@ -0,0 +1,171 @@
+use std::{cell::RefCell, collections::HashMap, iter::once};
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::structs::{
+    rule::{Call, Expr},
+    scope::Scope,
+    table::{Answer, TableStack, TabledCall},
+    value::Handle,
+};
+
+use super::{
+    environment::Database,
+    error::Error,
+    query::{query_relation, resolve},
+    stratify::Key,
+};
+
+/// Names the variables of a term by their order of appearance.
+fn variant_expr(this: &Expr, record: &mut Vec<Handle<Symbol>>) -> Expr {
+    match this {
+        Expr::Value(_) => this.clone(),
+        Expr::Variable(k) => {
+            let i = record.iter().position(|x| x == k).unwrap_or_else(|| {
+                record.push(k.clone());
+                record.len() - 1
+            });
+            Expr::Variable(Handle::new(Symbol::new(&format!("#{}", i))))
+        }
+        Expr::FunctionCall(c) => Expr::FunctionCall(Handle::new(Call {
+            call_name: c.call_name.clone(),
+            args: c.args.iter().map(|x| variant_expr(x, record)).collect(),
+        })),
+        Expr::Pair(p) => Expr::Pair(Handle::new((variant_expr(&p.0, record), variant_expr(&p.1, record)))),
+        Expr::Tuple(t) => Expr::Tuple(t.iter().map(|x| variant_expr(x, record)).collect()),
+        Expr::Dict(entries, rest) => Expr::Dict(
+            entries.iter().map(|(k, x)| (k.clone(), variant_expr(x, record))).collect(),
+            rest.as_ref().map(|x| Handle::new(variant_expr(x, record))),
+        ),
+    }
+}
+
+/// The args with their variables named by order of appearance,
+/// the same for calls or answers equal up to renaming.
+fn variant(args: &[Expr]) -> Handle<[Expr]> {
+    let mut record = vec![];
+    args.iter().map(|x| variant_expr(x, &mut record)).collect()
+}
+
+thread_local! {
+    /// The tabled calls being evaluated on this thread, by database.
+    /// A query evaluates its tabled calls on the thread it is iterated on.
+    static EVALUATIONS: RefCell<HashMap<usize, TableStack>> = RefCell::new(HashMap::new());
+}
+
+/// Runs `f` on the tabled calls the current query evaluates on the database.
+fn with_stack<R>(db: &Handle<Database>, f: impl FnOnce(&mut TableStack) -> R) -> R {
+    let id = Handle::as_ptr(db) as usize;
+    EVALUATIONS.with(|x| {
+        let mut record = x.borrow_mut();
+        let r = record.entry(id).or_insert_with(|| TableStack {
+            generation: db.tables.read().unwrap().generation,
+            ..Default::default()
+        });
+        f(r)
+    })
+}
+
+/// Ends the evaluation of the tabled calls of the query, once no call is left in it.
+fn leave(db: &Handle<Database>) {
+    let id = Handle::as_ptr(db) as usize;
+    EVALUATIONS.with(|x| {
+        let mut record = x.borrow_mut();
+        if record.get(&id).is_some_and(|r| r.stack.is_empty()) {
+            record.remove(&id);
+        }
+    })
+}
+
+/// The answers of a tabled call, as terms for its args.
+/// A call made again while it is being evaluated gets the answers found
+/// so far, the first call is then evaluated again until no table grows.
+/// Calls using each other's answers complete together with the lowest of them.
+/// Completed tables are shared with the other queries, unless the database
+/// changed meanwhile. On an error the incomplete tables of the calls are dropped.
+pub fn solve_tabled(key: &Key, args: &Handle<[Expr]>, db: &Handle<Database>) -> Result<Vec<Answer>, Error> {
+    let call: TabledCall = (key.clone(), variant(args));
+    if let Some(x) = db.tables.read().unwrap().tables.get(&call) {
+        return Ok(x.to_vec());
+    }
+    let found = with_stack(db, |record| {
+        let table = record.tables.get(&call)?;
+        if !table.complete {
+            let i = record.stack.iter().position(|x| x == &call)?;
+            record.low = record.low.min(i);
+        }
+        Some(table.answers.clone())
+    });
+    if let Some(x) = found {
+        return Ok(x);
+    }
+    let (index, outer_low, pending) = with_stack(db, |record| {
+        record.tables.entry(call.clone()).or_default();
+        record.stack.push(call.clone());
+        let r = (record.stack.len() - 1, record.low, record.pending.len());
+        record.low = usize::MAX;
+        r
+    });
+    loop {
+        let before = with_stack(db, |record| record.added);
+        let answers: Result<Vec<Answer>, Error> = query_relation(key.clone(), db, &Scope::new(), args.clone())
+            .map(|s| {
+                let s = s?;
+                let r: Vec<_> = args.iter().map(|x| resolve(x, &s)).collect();
+                Ok(variant(&r))
+            })
+            .collect();
+        let answers = match answers {
+            Ok(x) => x,
+            Err(e) => {
+                with_stack(db, |record| {
+                    record.stack.pop();
+                    let done: Vec<TabledCall> = record.pending.drain(pending..).collect();
+                    for x in done.iter().chain(once(&call)) {
+                        record.tables.remove(x);
+                    }
+                    record.low = outer_low;
+                });
+                leave(db);
+                return Err(e);
+            }
+        };
+        let added = with_stack(db, |record| {
+            let table = record.tables.get_mut(&call).unwrap();
+            for x in answers {
+                if table.seen.insert(x.clone()) {
+                    table.answers.push(x);
+                    record.added += 1;
+                }
+            }
+            record.added
+        });
+        if added == before {
+            break;
+        }
+    }
+    let (answers, generation, done) = with_stack(db, |record| {
+        record.stack.pop();
+        let low = record.low;
+        let mut done = vec![];
+        if low < index {
+            // complete once the call it depends on is
+            record.pending.push(call.clone());
+            record.low = outer_low.min(low);
+        } else {
+            for x in record.pending.drain(pending..).chain(once(call.clone())) {
+                let table = record.tables.get_mut(&x).unwrap();
+                table.complete = true;
+                done.push((x, table.answers.as_slice().into()));
+            }
+            record.low = outer_low;
+        }
+        (record.tables[&call].answers.clone(), record.generation, done)
+    });
+    leave(db);
+    let mut record = db.tables.write().unwrap();
+    if record.generation == generation {
+        record.tables.extend(done);
+    }
+    Ok(answers)
+}
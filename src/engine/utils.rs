@@ -19,17 +19,43 @@ impl_pattern!(RULE_PATTERN, "('rule prarms exprs ...)");
 
 impl_pattern!(QUERY_PATTERN, "('query prarms exprs ...)");
 
+impl_pattern!(TABLE_PATTERN, "('table name arity)");
+
+impl_pattern!(BAG_PATTERN, "('bag name arity)");
+
+impl_pattern!(RETRACT_PATTERN, "('retract name exprs ...)");
+
+impl_pattern!(INDEX_PATTERN, "('index name arity cols)");
+
 impl_pattern!(RULE_PARAMS_PATTERN, "(name args ...)");
 
 impl_pattern!(FACT_QUERY_PATTERN, "(name args ...)");
 
+impl_pattern!(NOT_GOAL_PATTERN, "('not goal)");
+
+impl_pattern!(OR_GOAL_PATTERN, "('or goals ...)");
+
+impl_pattern!(COUNT_GOAL_PATTERN, "('count result goal)");
+
+impl_pattern!(AGGREGATE_GOAL_PATTERN, "(op result value goal)");
+
 impl_pattern!(FUNCTION_CALL_PATTERN, "(name args ...)");
 
 impl_pattern!(QUERY_PARAMS_PATTERN, "(args ...)");
 
 impl_pattern!(SYMBOL_LITERIAL_PATTERN, "('quote sym)");
 
+impl_pattern!(TYPED_LITERIAL_PATTERN, "(type text)");
+
+impl_pattern!(QUOTED_PAIR_PATTERN, "(items ... . tail)");
+
+impl_pattern!(QUOTED_LIST_PATTERN, "(items ...)");
+
 impl_pattern!(TUPLE_PATTERN_PATTERN, "('tuple args ...)");
 
 impl_pattern!(LIST_HAS_EXTEND_PATTERN_PATTERN, "('list args ... . extend)");
-impl_pattern!(LIST_PATTERN_PATTERN, "('list args ... . extends)");
+impl_pattern!(LIST_PATTERN_PATTERN, "('list args ...)");
+
+impl_pattern!(DICT_HAS_REST_PATTERN, "('dict entries ... . rest)");
+impl_pattern!(DICT_PATTERN, "('dict entries ...)");
+impl_pattern!(DICT_ENTRY_PATTERN, "(key . value)");
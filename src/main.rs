@@ -1,13 +1,10 @@
-mod engine;
-mod structs;
-
 use std::io::{stdin, stdout, Write};
 
 use sexpr_ir::syntax::sexpr::one_unit_parse;
-use structs::{scope::Scope, value::Handle};
+use libakasha::structs::{scope::Scope, value::Handle};
 
-use crate::engine::environment::Database;
-use crate::engine::load::repl_eval;
+use libakasha::engine::environment::Database;
+use libakasha::engine::load::repl_eval;
 
 fn start_repl(env: &Handle<Database>, scope: &Handle<Scope>) {
     loop {
@@ -26,15 +23,23 @@ fn start_repl(env: &Handle<Database>, scope: &Handle<Scope>) {
         }
         let input = r.unwrap();
 
-        if let Some(rs) = repl_eval(env, scope, &input) {
-            if let Some(x) = rs {
-                for (k, v) in x.0.read().unwrap().iter() {
-                    println!("{}: {}", k.0, v);
+        match repl_eval(env, scope, &input) {
+            Ok(rs) => {
+                if let Some(rows) = rs {
+                    for x in rows.iter() {
+                        let r = x
+                            .iter()
+                            .map(|(k, v)| format!("{}: {}", k.0, v))
+                            .collect::<Vec<_>>();
+                        println!("{}", r.join(", "));
+                    }
+                    if rows.is_empty() {
+                        println!("no solution.");
+                    }
                 }
+                println!("ok.");
             }
-            println!("ok.");
-        } else {
-            println!("err.");
+            Err(e) => println!("err: {}", e),
         }
     }
 }
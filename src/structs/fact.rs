@@ -1,14 +1,150 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use sexpr_ir::gast::{symbol::Symbol, Handle};
 
 use super::value::Value;
 
+/// The tables are shared by the records cloned from one another,
+/// a shared table is copied when it changes.
 #[derive(Debug, Default, Clone)]
-pub struct FactRecord(pub HashMap<(Handle<Symbol>, usize), ValueTable>);
+pub struct FactRecord(pub HashMap<(Handle<Symbol>, usize), Handle<ValueTable>>);
 
-#[derive(Debug, Clone)]
-pub struct ValueTable(pub Vec<ValueLine>);
+#[derive(Debug, Default, Clone)]
+pub struct ValueTable {
+    /// The lines by id, `None` once removed. The id of a line never changes,
+    /// the ids of removed lines are given to new ones.
+    pub lines: Vec<Option<ValueLine>>,
+    /// The ids of the removed lines.
+    pub free: Vec<usize>,
+    /// The ids of the lines by the values of a set of columns.
+    pub indexes: HashMap<Columns, ValueIndex>,
+}
+
+/// The columns of an index, by position.
+pub type Columns = Handle<[usize]>;
 
 #[derive(Debug, Clone)]
 pub struct ValueLine(pub Handle<[Value]>);
+
+#[derive(Debug, Default, Clone)]
+pub struct ValueIndex(pub HashMap<Vec<Value>, Vec<usize>>);
+
+/// The indexes declared on relations without facts yet, by name and arity.
+/// A relation gets them with its first fact.
+#[derive(Debug, Default, Clone)]
+pub struct IndexRecord(pub HashMap<(Handle<Symbol>, usize), Vec<Columns>>);
+
+/// The relations whose facts are kept with duplicates, by name and arity.
+#[derive(Debug, Default, Clone)]
+pub struct BagRecord(pub HashSet<(Handle<Symbol>, usize)>);
+
+fn index_key(cols: &[usize], line: &ValueLine) -> Vec<Value> {
+    cols.iter().map(|i| line.0[*i].clone()).collect()
+}
+
+impl ValueTable {
+    /// An empty table, with an index on each of its columns and one on all of them.
+    pub fn new(arity: usize) -> Self {
+        let mut r = ValueTable::default();
+        (0..arity).for_each(|i| r.add_index(&[i]));
+        r.add_index(&(0..arity).collect::<Vec<_>>());
+        r
+    }
+
+    /// The number of lines.
+    pub fn len(&self) -> usize {
+        self.lines.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: usize) -> Option<&ValueLine> {
+        self.lines.get(id)?.as_ref()
+    }
+
+    /// The lines with their ids, by id.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &ValueLine)> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, line)| Some((i, line.as_ref()?)))
+    }
+
+    /// Adds an index on the columns, over the lines already in the table.
+    pub fn add_index(&mut self, cols: &[usize]) {
+        if self.indexes.contains_key(cols) {
+            return;
+        }
+        let mut index = ValueIndex::default();
+        for (i, line) in self.iter() {
+            index.0.entry(index_key(cols, line)).or_default().push(i);
+        }
+        self.indexes.insert(cols.into(), index);
+    }
+
+    /// Removes the lines of the ids, and their ids from the buckets of the indexes holding them.
+    pub fn remove(&mut self, ids: &[usize]) {
+        for id in ids {
+            let line = match self.lines.get_mut(*id).and_then(Option::take) {
+                Some(x) => x,
+                None => continue,
+            };
+            for (cols, index) in self.indexes.iter_mut() {
+                let key = index_key(cols, &line);
+                if let Some(bucket) = index.0.get_mut(&key) {
+                    bucket.retain(|x| x != id);
+                    if bucket.is_empty() {
+                        index.0.remove(&key);
+                    }
+                }
+            }
+            self.free.push(*id);
+        }
+    }
+
+    pub fn contains(&self, line: &ValueLine) -> bool {
+        let bound: Vec<_> = line.0.iter().cloned().map(Some).collect();
+        match self.lookup(&bound) {
+            Some(x) => x.iter().any(|i| self.get(*i).is_some_and(|x| x.0 == line.0)),
+            None => self.iter().any(|(_, x)| x.0 == line.0),
+        }
+    }
+
+    /// Adds the line unless the table has it already, returns whether it was added.
+    pub fn insert(&mut self, line: ValueLine) -> bool {
+        if self.contains(&line) {
+            return false;
+        }
+        self.push(line);
+        true
+    }
+
+    /// Adds the line, even if the table has it already.
+    pub fn push(&mut self, line: ValueLine) {
+        let i = self.free.pop().unwrap_or(self.lines.len());
+        for (cols, index) in self.indexes.iter_mut() {
+            index.0.entry(index_key(cols, &line)).or_default().push(i);
+        }
+        if i == self.lines.len() {
+            self.lines.push(Some(line));
+        } else {
+            self.lines[i] = Some(line);
+        }
+    }
+
+    /// The ids of the lines which can match the bound columns, from
+    /// the index on bound columns giving the fewest. `None` if no index applies.
+    pub fn lookup(&self, bound: &[Option<Value>]) -> Option<Vec<usize>> {
+        self.indexes
+            .iter()
+            .filter(|(cols, _)| cols.iter().all(|i| matches!(bound.get(*i), Some(Some(_)))))
+            .map(|(cols, index)| {
+                let key: Vec<_> = cols.iter().map(|i| bound[*i].clone().unwrap()).collect();
+                index.0.get(&key).map_or(&[][..], Vec::as_slice)
+            })
+            .min_by_key(|x| x.len())
+            .map(<[usize]>::to_vec)
+    }
+}
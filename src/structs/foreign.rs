@@ -0,0 +1,45 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use super::value::{Handle, Value};
+
+#[derive(Debug, Default, Clone)]
+pub struct ForeignRecord(pub HashMap<(Handle<Symbol>, usize), ForeignRelation>);
+
+/// The tuples of a foreign relation, produced on demand.
+pub type ForeignTuples = Box<dyn Iterator<Item = Vec<Value>>>;
+
+/// Takes the arguments of the query, `None` for the unbound ones.
+/// The tuples returned need not match the bound arguments,
+/// they are unified with the query as facts are.
+pub type NativeRelation = dyn Fn(&[Option<Value>]) -> ForeignTuples + Send + Sync;
+
+#[derive(Clone)]
+pub struct ForeignRelation {
+    pub name: Handle<Symbol>,
+    pub arity: usize,
+    pub body: Handle<NativeRelation>,
+}
+
+impl Debug for ForeignRelation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ForeignRelation")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl ForeignRelation {
+    pub fn new<F>(name: &str, arity: usize, body: F) -> ForeignRelation
+    where
+        F: Fn(&[Option<Value>]) -> ForeignTuples + Send + Sync + 'static,
+    {
+        ForeignRelation {
+            name: Handle::new(Symbol::new(name)),
+            arity,
+            body: Handle::new(body),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use crate::engine::error::Error;
+
+use super::value::{Handle, Value};
+
+#[derive(Debug, Default, Clone)]
+pub struct FunctionRecord(pub HashMap<Handle<Symbol>, Function>);
+
+pub type NativeFunction = dyn Fn(&[Value]) -> Result<Value, Error> + Send + Sync;
+
+#[derive(Clone)]
+pub struct Function {
+    pub name: Handle<Symbol>,
+    /// `None` for functions taking any number of arguments.
+    pub arity: Option<usize>,
+    pub body: Handle<NativeFunction>,
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl Function {
+    pub fn new<F>(name: &str, arity: Option<usize>, body: F) -> Function
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        Function {
+            name: Handle::new(Symbol::new(name)),
+            arity,
+            body: Handle::new(body),
+        }
+    }
+
+    pub fn call(&self, args: &[Value]) -> Result<Value, Error> {
+        match self.arity {
+            Some(expected) if expected != args.len() => Err(Error::ArityMismatch {
+                name: self.name.clone(),
+                expected,
+                found: args.len(),
+            }),
+            _ => (self.body)(args),
+        }
+    }
+}
@@ -1,4 +1,8 @@
 pub mod fact;
+pub mod foreign;
+pub mod function;
+pub mod plan;
 pub mod rule;
 pub mod scope;
+pub mod table;
 pub mod value;
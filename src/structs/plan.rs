@@ -0,0 +1,23 @@
+use std::collections::{HashMap, HashSet};
+
+use sexpr_ir::gast::{symbol::Symbol, Handle};
+
+/// A rule, by the name and arity of its relation and its position among the rules of it.
+pub type RuleId = ((Handle<Symbol>, usize), usize);
+
+/// Relations by name and arity.
+pub type Relations = HashSet<(Handle<Symbol>, usize)>;
+
+/// The head variables of a rule bound by a call, by their names in the rule.
+pub type BoundHead = Vec<Handle<Symbol>>;
+
+/// What the planner found, kept until the database changes.
+#[derive(Debug, Default)]
+pub struct PlanRecord {
+    /// The order of the goals of a rule body, by the head variables bound by the call.
+    pub orders: HashMap<(RuleId, BoundHead), Handle<[usize]>>,
+    /// The relations whose solutions depend on which of their args are bound.
+    pub sensitive: Option<Handle<Relations>>,
+    /// Counts the changes of the database, what was found before one is not kept.
+    pub generation: usize,
+}
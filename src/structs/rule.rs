@@ -1,8 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use sexpr_ir::gast::{symbol::Symbol, Handle};
 
-use super::value::Value;
+use super::value::{Dict, Pair, Tuple, Value};
 
 #[derive(Debug, Default, Clone)]
 pub struct RuleRecord(pub HashMap<(Handle<Symbol>, usize), RuleTable>);
@@ -13,11 +13,14 @@ pub struct RuleTable(pub Vec<RuleBody>);
 #[derive(Debug, Clone)]
 pub struct RuleBody {
     pub prarms: Prarms,
-    pub bodys: Handle<[FactQuery]>,
+    pub bodys: Handle<[Goal]>,
 }
 
 pub type Prarms = Handle<[Pattern]>;
 
+/// The items of a dict term or pattern by key.
+pub type Entries<T> = Handle<[(Handle<String>, T)]>;
+
 #[derive(Debug, Clone)]
 pub enum Pattern {
     Ignore,
@@ -25,6 +28,41 @@ pub enum Pattern {
     Constant(Value),
     Tuple(Handle<[Pattern]>),
     List(Handle<[Pattern]>, Option<Handle<Pattern>>),
+    /// The patterns of some keys, with the pattern of a dict of the other keys.
+    /// Without it the dict has no other keys.
+    Dict(Entries<Pattern>, Option<Handle<Pattern>>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Goal {
+    Fact(FactQuery),
+    /// Succeeds when the inner goal has no solution.
+    Not(Handle<Goal>),
+    /// Succeeds once for each solution of each branch, in order.
+    Or(Handle<[Goal]>),
+    Aggregate(Handle<Aggregate>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateOp {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+    Collect,
+}
+
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    pub op: AggregateOp,
+    pub result: Expr,
+    /// The aggregated term, `None` for count.
+    pub value: Option<Expr>,
+    pub goal: Goal,
+    /// Variables of the goal which are also used outside of the aggregate,
+    /// the solutions of the goal are grouped by them.
+    pub group: Handle<[Handle<Symbol>]>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,15 +71,48 @@ pub struct FactQuery {
     pub args: Handle<[Expr]>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Expr {
     Value(Value),
     Variable(Handle<Symbol>),
     FunctionCall(Handle<Call>),
+    Pair(Handle<(Expr, Expr)>),
+    Tuple(Handle<[Expr]>),
+    /// The terms of some keys, with the term of a dict of the other keys.
+    Dict(Entries<Expr>, Option<Handle<Expr>>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Call {
     pub call_name: Handle<Symbol>,
     pub args: Box<[Expr]>,
 }
+
+impl Expr {
+    /// The value of a term without variables or calls.
+    pub fn to_value(&self) -> Option<Value> {
+        match self {
+            Expr::Value(v) => Some(v.clone()),
+            Expr::Variable(_) | Expr::FunctionCall(_) => None,
+            Expr::Pair(p) => {
+                let r = Pair(p.0.to_value()?, p.1.to_value()?);
+                Some(Value::Pair(Handle::new(r)))
+            }
+            Expr::Tuple(t) => {
+                let r: Option<Vec<_>> = t.iter().map(Expr::to_value).collect();
+                Some(Value::Tuple(Handle::new(Tuple(r?))))
+            }
+            Expr::Dict(entries, rest) => {
+                let mut r = match rest.as_ref().map(|x| x.to_value()) {
+                    Some(Some(Value::Dict(d))) => d.0.clone(),
+                    Some(_) => return None,
+                    None => BTreeMap::new(),
+                };
+                for (k, v) in entries.iter() {
+                    r.insert(k.clone(), v.to_value()?);
+                }
+                Some(Value::Dict(Handle::new(Dict(r))))
+            }
+        }
+    }
+}
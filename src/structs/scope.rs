@@ -4,29 +4,44 @@ use sexpr_ir::gast::{symbol::Symbol, Handle};
 
 use super::value::Value;
 
-#[derive(Debug, Clone, Default)]
-pub struct SimpleScope(pub Handle<RwLock<HashMap<Handle<Symbol>, Value>>>);
+#[derive(Debug, Clone)]
+pub struct SimpleScope<T = Value>(pub Handle<RwLock<HashMap<Handle<Symbol>, T>>>);
 
-#[derive(Debug, Clone, Default)]
-pub struct Scope {
-    pub this_level: SimpleScope,
-    pub parent: Option<Handle<Scope>>,
+#[derive(Debug, Clone)]
+pub struct Scope<T = Value> {
+    pub this_level: SimpleScope<T>,
+    pub parent: Option<Handle<Scope<T>>>,
 }
 
-impl SimpleScope {
-    pub fn new() -> SimpleScope {
+impl<T> Default for SimpleScope<T> {
+    fn default() -> Self {
         SimpleScope(Handle::new(RwLock::new(HashMap::new())))
     }
 }
 
-impl From<HashMap<Handle<Symbol>, Value>> for SimpleScope {
-    fn from(i: HashMap<Handle<Symbol>, Value>) -> Self {
+impl<T> Default for Scope<T> {
+    fn default() -> Self {
+        Scope {
+            this_level: SimpleScope::default(),
+            parent: None,
+        }
+    }
+}
+
+impl<T> SimpleScope<T> {
+    pub fn new() -> SimpleScope<T> {
+        SimpleScope(Handle::new(RwLock::new(HashMap::new())))
+    }
+}
+
+impl<T> From<HashMap<Handle<Symbol>, T>> for SimpleScope<T> {
+    fn from(i: HashMap<Handle<Symbol>, T>) -> Self {
         SimpleScope(Handle::new(RwLock::new(i)))
     }
 }
 
-impl Scope {
-    pub fn new() -> Handle<Scope> {
+impl<T: Clone> Scope<T> {
+    pub fn new() -> Handle<Scope<T>> {
         let r = Scope {
             this_level: SimpleScope::new(),
             parent: None,
@@ -34,7 +49,7 @@ impl Scope {
         Handle::new(r)
     }
 
-    pub fn from(this_level: SimpleScope) -> Handle<Scope> {
+    pub fn from(this_level: SimpleScope<T>) -> Handle<Scope<T>> {
         let r = Scope {
             this_level,
             parent: None,
@@ -42,7 +57,7 @@ impl Scope {
         Handle::new(r)
     }
 
-    pub fn new_level(self: &Handle<Scope>, this_level: SimpleScope) -> Handle<Scope> {
+    pub fn new_level(self: &Handle<Scope<T>>, this_level: SimpleScope<T>) -> Handle<Scope<T>> {
         let r = Scope {
             this_level,
             parent: Some(self.clone()),
@@ -50,16 +65,16 @@ impl Scope {
         Handle::new(r)
     }
 
-    pub fn set(self: &Handle<Scope>, k: &Handle<Symbol>, v: &Value) {
+    pub fn set(self: &Handle<Scope<T>>, k: &Handle<Symbol>, v: &T) {
         let mut record = self.this_level.0.write().unwrap();
         record.insert(k.clone(), v.clone());
     }
 
-    pub fn find_from_raw(&self, k: &str) -> Option<Value> {
+    pub fn find_from_raw(&self, k: &str) -> Option<T> {
         self.find(&Handle::new(Symbol::new(k)))
     }
 
-    pub fn find(&self, k: &Handle<Symbol>) -> Option<Value> {
+    pub fn find(&self, k: &Handle<Symbol>) -> Option<T> {
         let record = self.this_level.0.read().unwrap();
         if let Some(r) = record.get(k) {
             Some(r.clone())
@@ -68,7 +83,7 @@ impl Scope {
         }
     }
 
-    pub fn flatten(&self) -> SimpleScope {
+    pub fn flatten(&self) -> SimpleScope<T> {
         if let Some(p) = &self.parent {
             let p = p.flatten();
             let mut record = p.0.write().unwrap();
@@ -0,0 +1,46 @@
+use std::collections::{HashMap, HashSet};
+
+use sexpr_ir::gast::{symbol::Symbol, Handle};
+
+use super::rule::Expr;
+
+/// The relations declared tabled, by name and arity.
+#[derive(Debug, Default, Clone)]
+pub struct TabledRecord(pub HashSet<(Handle<Symbol>, usize)>);
+
+/// A call of a tabled relation, with the variables of its args named by order of appearance.
+pub type TabledCall = ((Handle<Symbol>, usize), Handle<[Expr]>);
+
+/// The answers of a tabled call, as terms for its args.
+pub type Answer = Handle<[Expr]>;
+
+/// The answers of the completed tabled calls, shared by the queries until the database changes.
+#[derive(Debug, Default)]
+pub struct TableRecord {
+    pub tables: HashMap<TabledCall, Handle<[Answer]>>,
+    /// Counts the changes of the database, tables computed before one are not shared.
+    pub generation: usize,
+}
+
+/// The tabled calls evaluated by one query.
+#[derive(Debug, Default)]
+pub struct TableStack {
+    pub tables: HashMap<TabledCall, AnswerTable>,
+    /// The calls being evaluated, the innermost last.
+    pub stack: Vec<TabledCall>,
+    /// The lowest index in `stack` whose answers were used while incomplete.
+    pub low: usize,
+    /// Evaluated calls waiting for a call lower in `stack` to complete.
+    pub pending: Vec<TabledCall>,
+    /// The number of answers added so far, a fixpoint adds none.
+    pub added: usize,
+    /// The generation of the database when the evaluation started.
+    pub generation: usize,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct AnswerTable {
+    pub answers: Vec<Answer>,
+    pub seen: HashSet<Answer>,
+    pub complete: bool,
+}
@@ -1,10 +1,21 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    mem::discriminant,
+};
 
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
 use sexpr_ir::gast::symbol::Symbol;
 
 pub type Handle<T> = sexpr_ir::gast::Handle<T>;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Value {
     Nil,
     Bool(bool),
@@ -12,10 +23,250 @@ pub enum Value {
     Uint(u64),
     Int(i64),
     Float(f64),
+    /// An integer of any size.
+    BigInt(Handle<BigInt>),
+    /// An exact decimal number.
+    Decimal(Handle<BigDecimal>),
     Str(Handle<String>),
     Sym(Handle<Symbol>),
     Pair(Handle<Pair>),
     Tuple(Handle<Tuple>),
+    Dict(Handle<Dict>),
+    Bytes(Handle<Vec<u8>>),
+    /// A UTC instant, with nanosecond precision.
+    Timestamp(DateTime<Utc>),
+    Duration(Duration),
+}
+
+/// Structural equality, where all NaNs are equal to each other
+/// and -0.0 is equal to 0.0, so that facts form sets.
+/// Integers and decimals are equal by value, as 10 and `(decimal "10.0")`,
+/// floats are different values from them, as 1 and 1.0.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Uint(a), Value::Uint(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b || a.is_nan() && b.is_nan(),
+            _ if self.is_exact() && other.is_exact() => self.cmp_number(other) == Some(Ordering::Equal),
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Sym(a), Value::Sym(b)) => a == b,
+            (Value::Pair(a), Value::Pair(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::Dict(a), Value::Dict(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            (Value::Timestamp(a), Value::Timestamp(b)) => a == b,
+            (Value::Duration(a), Value::Duration(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+/// Hashes an integer by its value, the same whatever the type holding it.
+fn hash_integer<H: Hasher>(this: &BigInt, state: &mut H) {
+    match this.to_i128() {
+        Some(x) => x.hash(state),
+        None => this.hash(state),
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // the exact numbers equal by value share a hash
+        if self.is_exact() {
+            discriminant(&Value::Uint(0)).hash(state);
+        } else {
+            discriminant(self).hash(state);
+        }
+        match self {
+            Value::Nil => {}
+            Value::Bool(x) => x.hash(state),
+            Value::Char(x) => x.hash(state),
+            Value::Uint(x) => (*x as i128).hash(state),
+            Value::Int(x) => (*x as i128).hash(state),
+            Value::Float(x) if x.is_nan() => f64::NAN.to_bits().hash(state),
+            Value::Float(x) if *x == 0.0 => 0u64.hash(state),
+            Value::Float(x) => x.to_bits().hash(state),
+            Value::BigInt(x) => hash_integer(x, state),
+            Value::Decimal(x) if x.is_integer() => hash_integer(&x.with_scale(0).into_bigint_and_exponent().0, state),
+            Value::Decimal(x) => x.normalized().hash(state),
+            Value::Str(x) => x.hash(state),
+            Value::Sym(x) => x.hash(state),
+            Value::Pair(x) => x.hash(state),
+            Value::Tuple(x) => x.hash(state),
+            Value::Dict(x) => x.hash(state),
+            Value::Bytes(x) => x.hash(state),
+            Value::Timestamp(x) => x.hash(state),
+            Value::Duration(x) => x.hash(state),
+        }
+    }
+}
+
+/// A total order over values:
+/// Nil < Bool < numbers < Char < Str < Sym < Pair < Tuple < Dict < Bytes < Timestamp < Duration.
+/// Numbers compare by value across their types, with NaN above all of them,
+/// a float is above the integer or decimal equal to it.
+/// Pairs and tuples compare item by item, dicts entry by entry in key order,
+/// bytes lexicographically, and timestamps and durations in time.
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Char(a), Value::Char(b)) => a.cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            (Value::Sym(a), Value::Sym(b)) => a.0.cmp(&b.0),
+            (Value::Pair(a), Value::Pair(b)) => (&a.0, &a.1).cmp(&(&b.0, &b.1)),
+            (Value::Tuple(a), Value::Tuple(b)) => a.0.cmp(&b.0),
+            (Value::Dict(a), Value::Dict(b)) => a.0.cmp(&b.0),
+            (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.cmp(b),
+            (Value::Duration(a), Value::Duration(b)) => a.cmp(b),
+            _ if self.rank() == 2 && other.rank() == 2 => {
+                let nan = |x: &Value| matches!(x, Value::Float(f) if f.is_nan());
+                self.cmp_number(other)
+                    .unwrap_or_else(|| nan(self).cmp(&nan(other)))
+                    .then_with(|| self.is_float().cmp(&other.is_float()))
+            }
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|x| match x {
+            [a, b] => u8::from_str_radix(&format!("{}{}", *a as char, *b as char), 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+const DURATION_UNITS: [(&str, i128); 7] = [
+    ("d", 86_400_000_000_000),
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+pub(crate) fn duration_from_nanos(nanos: i128) -> Option<Duration> {
+    let secs = i64::try_from(nanos.div_euclid(1_000_000_000)).ok()?;
+    Duration::new(secs, nanos.rem_euclid(1_000_000_000) as u32)
+}
+
+pub(crate) fn duration_nanos(this: &Duration) -> i128 {
+    this.num_seconds() as i128 * 1_000_000_000 + this.subsec_nanos() as i128
+}
+
+/// A duration as amounts of units, as `1h30m` or `-2.5s`,
+/// the units are d, h, m, s, ms, us and ns.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (negative, mut rest) = match text.strip_prefix('-') {
+        Some(x) => (true, x),
+        None => (false, text),
+    };
+    if rest.is_empty() {
+        return None;
+    }
+    let mut r: i128 = 0;
+    while !rest.is_empty() {
+        let n = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let u = rest[n..].find(|c: char| c.is_ascii_digit()).map_or(rest.len(), |x| x + n);
+        let (number, unit) = (&rest[..n], &rest[n..u]);
+        let (_, scale) = DURATION_UNITS.iter().find(|(x, _)| *x == unit)?;
+        let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+        if int.is_empty() && frac.is_empty() || frac.contains('.') {
+            return None;
+        }
+        let int: i128 = if int.is_empty() { 0 } else { int.parse().ok()? };
+        let mut x = int.checked_mul(*scale)?;
+        // the fraction below a nanosecond is dropped
+        let mut place = *scale;
+        for c in frac.chars() {
+            place /= 10;
+            x += (c as i128 - '0' as i128) * place;
+        }
+        r = r.checked_add(x)?;
+        rest = &rest[u..];
+    }
+    duration_from_nanos(if negative { -r } else { r })
+}
+
+/// The text of a duration read by `parse_duration`, in days, hours,
+/// minutes and seconds, as `1d2h3m4.5s`.
+fn duration_text(this: &Duration) -> String {
+    let nanos = duration_nanos(this);
+    let mut rest = nanos.abs();
+    let mut r = String::new();
+    if nanos < 0 {
+        r.push('-');
+    }
+    for (unit, scale) in DURATION_UNITS[..3].iter() {
+        if rest >= *scale {
+            r.push_str(&format!("{}{}", rest / scale, unit));
+            rest %= scale;
+        }
+    }
+    if rest > 0 || nanos == 0 {
+        let frac = format!("{:09}", rest % 1_000_000_000);
+        let frac = frac.trim_end_matches('0');
+        r.push_str(&(rest / 1_000_000_000).to_string());
+        if !frac.is_empty() {
+            r.push('.');
+            r.push_str(frac);
+        }
+        r.push('s');
+    }
+    r
+}
+
+/// Compares a number other than Float with a float exactly. `None` for NaN.
+fn cmp_exact_float(a: &Value, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        return None;
+    }
+    match a {
+        Value::Uint(x) => Some(cmp_int_float(*x as i128, b)),
+        Value::Int(x) => Some(cmp_int_float(*x as i128, b)),
+        _ => {
+            let a = a.to_decimal()?;
+            match BigDecimal::try_from(b) {
+                Ok(b) => Some(a.cmp(&b)),
+                // infinite
+                Err(_) => Some(if b > 0.0 { Ordering::Less } else { Ordering::Greater }),
+            }
+        }
+    }
+}
+
+/// Compares an integer with a float exactly, the float is not NaN.
+fn cmp_int_float(a: i128, b: f64) -> Ordering {
+    // beyond the range of i128, the sign of the float decides
+    if b >= 2f64.powi(127) {
+        return Ordering::Less;
+    }
+    if b < -(2f64.powi(127)) {
+        return Ordering::Greater;
+    }
+    let t = b.trunc();
+    a.cmp(&(t as i128)).then_with(|| t.partial_cmp(&b).unwrap())
 }
 
 impl Display for Value {
@@ -27,68 +278,97 @@ impl Display for Value {
             Value::Uint(v) => write!(f, "{}", v),
             Value::Int(v) => write!(f, "{}", v),
             Value::Float(v) => write!(f, "{}", v),
+            Value::BigInt(v) => write!(f, "(bigint \"{}\")", v),
+            Value::Decimal(v) => write!(f, "(decimal \"{}\")", v),
             Value::Str(v) => write!(f, "\"{}\"", v),
             Value::Sym(v) => write!(f, "{}", v),
             Value::Char(v) => write!(f, "(char \"{}\")", v),
             Value::Pair(v) => v.fmt(f),
             Value::Tuple(v) => v.fmt(f),
+            Value::Dict(v) => v.fmt(f),
+            Value::Bytes(v) => {
+                let r: String = v.iter().map(|x| format!("{:02x}", x)).collect();
+                write!(f, "(bytes \"{}\")", r)
+            }
+            Value::Timestamp(v) => write!(f, "(timestamp \"{}\")", v.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            Value::Duration(v) => write!(f, "(duration \"{}\")", duration_text(v)),
         }
     }
 }
 
-impl Display for Pair {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// Writes a value as it is read back in an expression, where a symbol is quoted.
+fn fmt_expr(v: &Value, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match v {
+        Value::Sym(x) => write!(f, "'{}", x),
+        _ => v.fmt(f),
+    }
+}
+
+impl Pair {
+    /// Writes the items of the list, as the items of a quoted list or as expressions.
+    fn fmt_items(&self, f: &mut std::fmt::Formatter<'_>, quoted: bool) -> std::fmt::Result {
+        let item = |v: &Value, f: &mut std::fmt::Formatter<'_>| match v {
+            Value::Pair(x) if quoted => {
+                write!(f, "(")?;
+                x.fmt_items(f, quoted)?;
+                write!(f, ")")
+            }
+            _ if quoted => v.fmt(f),
+            _ => fmt_expr(v, f),
+        };
         let mut this = self;
-        write!(f, "'(")?;
-        let mut start = true;
         loop {
-            match this {
-                Pair(v, Value::Pair(t)) => {
-                    if !start {
-                        write!(f, " ")?;
-                    }
-                    v.fmt(f)?;
+            item(&this.0, f)?;
+            match &this.1 {
+                Value::Pair(t) => {
+                    write!(f, " ")?;
                     this = t;
-                    start = false;
-                    continue;
-                }
-                Pair(v, Value::Nil) => {
-                    if !start {
-                        write!(f, " ")?;
-                    }
-                    v.fmt(f)?;
-                    break;
                 }
-                Pair(v, t) => {
-                    if !start {
-                        write!(f, " ")?;
-                    }
-                    v.fmt(f)?;
+                Value::Nil => return Ok(()),
+                t => {
                     write!(f, " . ")?;
-                    t.fmt(f)?;
-                    break;
+                    return item(t, f);
                 }
             }
         }
+    }
+}
+
+/// A list is quoted when its items read back quoted,
+/// otherwise, as when it holds a typed literal, it is written as a `(list ...)` call.
+impl Display for Pair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_quotable() && self.1.is_quotable() {
+            write!(f, "'(")?;
+            self.fmt_items(f, true)?;
+        } else {
+            write!(f, "(list ")?;
+            self.fmt_items(f, false)?;
+        }
         write!(f, ")")
     }
 }
 
 impl Display for Tuple {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let r = self.0.iter().map(Value::to_string).collect::<Vec<_>>();
-        write!(f, "(vec {})", r.join(" "))
+        write!(f, "(vec")?;
+        for x in self.0.iter() {
+            write!(f, " ")?;
+            fmt_expr(x, f)?;
+        }
+        write!(f, ")")
     }
 }
 
 impl Display for Dict {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let r = self
-            .0
-            .iter()
-            .map(|(k, v)| format!("'(\"{}\" . {})", k, v))
-            .collect::<Vec<_>>();
-        write!(f, "(dict {})", r.join(" "))
+        write!(f, "(dict")?;
+        for (k, v) in self.0.iter() {
+            write!(f, " (\"{}\" . ", k)?;
+            fmt_expr(v, f)?;
+            write!(f, ")")?;
+        }
+        write!(f, ")")
     }
 }
 
@@ -109,19 +389,123 @@ impl Value {
     impl_is_type!(is_int, Int);
     impl_is_type!(is_uint, Uint);
     impl_is_type!(is_float, Float);
+    impl_is_type!(is_bigint, BigInt);
+    impl_is_type!(is_decimal, Decimal);
     impl_is_type!(is_str, Str);
     impl_is_type!(is_sym, Sym);
     impl_is_type!(is_pair, Pair);
-    impl_is_type!(is_dict, Tuple);
+    impl_is_type!(is_tuple, Tuple);
+    impl_is_type!(is_dict, Dict);
+    impl_is_type!(is_bytes, Bytes);
+    impl_is_type!(is_timestamp, Timestamp);
+    impl_is_type!(is_duration, Duration);
+
+    /// The integer as a Uint, or an Int if negative, as an integer literal is read,
+    /// and a BigInt only when it fits in neither.
+    pub fn from_bigint(x: BigInt) -> Value {
+        if let Some(x) = x.to_u64() {
+            Value::Uint(x)
+        } else if let Some(x) = x.to_i64() {
+            Value::Int(x)
+        } else {
+            Value::BigInt(Handle::new(x))
+        }
+    }
+
+    /// Reads the text of a literal written as `(type "text")`,
+    /// as `(bigint "12")` or `(timestamp "2021-05-01T00:00:00Z")`.
+    /// `None` if the type has no such literal or the text is not one of it.
+    pub fn from_literal(tp: &str, text: &str) -> Option<Value> {
+        match tp {
+            "char" => {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(Value::Char(c)),
+                    _ => None,
+                }
+            }
+            "bigint" => text.parse().ok().map(Value::from_bigint),
+            "decimal" => text.parse().ok().map(|x| Value::Decimal(Handle::new(x))),
+            "bytes" => parse_hex(text).map(|x| Value::Bytes(Handle::new(x))),
+            "timestamp" => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|x| Value::Timestamp(x.with_timezone(&Utc))),
+            "duration" => parse_duration(text).map(Value::Duration),
+            _ => None,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Nil => 0,
+            Value::Bool(_) => 1,
+            Value::Uint(_) | Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) | Value::Float(_) => 2,
+            Value::Char(_) => 3,
+            Value::Str(_) => 4,
+            Value::Sym(_) => 5,
+            Value::Pair(_) => 6,
+            Value::Tuple(_) => 7,
+            Value::Dict(_) => 8,
+            Value::Bytes(_) => 9,
+            Value::Timestamp(_) => 10,
+            Value::Duration(_) => 11,
+        }
+    }
+
+    /// Whether the value reads back from a quoted list, as the items of its own.
+    fn is_quotable(&self) -> bool {
+        match self {
+            Value::Nil | Value::Bool(_) | Value::Uint(_) | Value::Int(_) | Value::Float(_) => true,
+            Value::Str(_) | Value::Sym(_) => true,
+            Value::Pair(x) => x.0.is_quotable() && x.1.is_quotable(),
+            _ => false,
+        }
+    }
+
+    /// Whether the value is a number other than Float.
+    fn is_exact(&self) -> bool {
+        matches!(self, Value::Uint(_) | Value::Int(_) | Value::BigInt(_) | Value::Decimal(_))
+    }
+
+    /// The exact value of a number other than Float.
+    pub fn to_decimal(&self) -> Option<BigDecimal> {
+        match self {
+            Value::Uint(x) => Some(BigDecimal::from(*x)),
+            Value::Int(x) => Some(BigDecimal::from(*x)),
+            Value::BigInt(x) => Some(BigDecimal::from(BigInt::clone(x))),
+            Value::Decimal(x) => Some(BigDecimal::clone(x)),
+            _ => None,
+        }
+    }
+
+    /// Compares two numbers by value, exactly across all their types.
+    /// `None` if either is not a number or is NaN.
+    pub fn cmp_number(&self, other: &Value) -> Option<Ordering> {
+        let int = |x: &Value| match x {
+            Value::Uint(x) => Some(*x as i128),
+            Value::Int(x) => Some(*x as i128),
+            _ => None,
+        };
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Float(a), b) => cmp_exact_float(b, *a).map(Ordering::reverse),
+            (a, Value::Float(b)) => cmp_exact_float(a, *b),
+            (a, b) => match (int(a), int(b)) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => Some(a.to_decimal()?.cmp(&b.to_decimal()?)),
+            },
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Pair(pub Value, pub Value);
 
-#[derive(Debug, Clone)]
-pub struct Dict(pub HashMap<Handle<String>, Value>);
+/// A record of values by string keys, kept in key order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Dict(pub BTreeMap<Handle<String>, Value>);
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Tuple(pub Vec<Value>);
 
 impl From<&[Value]> for Value {
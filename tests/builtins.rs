@@ -0,0 +1,92 @@
+mod common;
+
+use libakasha::engine::error::Error;
+
+use common::{eval, program, query};
+
+#[test]
+fn builtin_functions() {
+    let (db, scope) = program(&[]);
+    let cases = [
+        ("(query (x) (= x (+ 1 2 3)))", "x: 6"),
+        ("(query (x) (= x (- 10 4)))", "x: 6"),
+        ("(query (x) (= x (* 2 3)))", "x: 6"),
+        ("(query (x) (= x (/ 7 2)))", "x: 3"),
+        ("(query (x) (= x (mod 7 4)))", "x: 3"),
+        ("(query (x) (= x (concat \"ab\" \"c\")))", "x: \"abc\""),
+        ("(query (x) (= x (string-length \"abc\")))", "x: 3"),
+        ("(query (x) (= x (car (list 1 2))))", "x: 1"),
+        ("(query (x) (= x (length (list 1 2))))", "x: 2"),
+        ("(query (x) (= x (nth (list 1 2) 1)))", "x: 2"),
+        ("(query (x) (= x (and true (not false))))", "x: true"),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(query(&db, &scope, input).unwrap(), [expected], "{}", input);
+    }
+}
+
+#[test]
+fn typed_errors() {
+    let (db, scope) = program(&[
+        "(fact num 1)",
+        "(fact num 2)",
+        "(fact parent 1 2)",
+        "(rule (half x y) (num x) (= y (/ x 0)))",
+    ]);
+    assert_eq!(eval(&db, &scope, "(fact p (/ 1 0))"), Err(Error::DivisionByZero));
+    assert_eq!(query(&db, &scope, "(query (x) (= x (/ 1 0)))"), Err(Error::DivisionByZero));
+    assert_eq!(query(&db, &scope, "(query (y) (num x) (= y (/ x 0)))"), Err(Error::DivisionByZero));
+    assert_eq!(query(&db, &scope, "(query (y) (half 1 y))"), Err(Error::DivisionByZero));
+    assert!(matches!(
+        query(&db, &scope, "(query (x) (parent x (car 1)))"),
+        Err(Error::TypeMismatch { .. })
+    ));
+    assert!(matches!(
+        query(&db, &scope, "(query (x) (= x (+ 1 \"a\")))"),
+        Err(Error::TypeMismatch { .. })
+    ));
+    assert!(matches!(
+        query(&db, &scope, "(query (x) (= x (undefined 1)))"),
+        Err(Error::UndefinedFunction(_))
+    ));
+    assert!(matches!(
+        query(&db, &scope, "(query (x) (= x (mod 1)))"),
+        Err(Error::ArityMismatch { expected: 2, found: 1, .. })
+    ));
+    assert!(matches!(
+        query(&db, &scope, "(query (x) (= x (nth (list 1) 5)))"),
+        Err(Error::IndexOutOfRange { length: 1, .. })
+    ));
+    assert_eq!(query(&db, &scope, "(query (x y) (num x) (= y (+ x 1)))").unwrap(), ["x: 1, y: 2", "x: 2, y: 3"]);
+    // a call on a variable not bound yet has no solution rather than an error
+    assert_eq!(query(&db, &scope, "(query (x y) (= y (+ x 1)) (num x))").unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn comparison_goals() {
+    let (db, scope) = program(&[
+        "(fact age 'ann 70)",
+        "(fact age 'bob 15)",
+        "(fact age 'cid 40)",
+        "(rule (adult x) (age x a) (>= a 18))",
+        "(rule (older x y) (age x a) (age y b) (> a b))",
+    ]);
+    assert_eq!(query(&db, &scope, "(query (x) (adult x))").unwrap(), ["x: ann", "x: cid"]);
+    assert_eq!(query(&db, &scope, "(query (x) (older x 'cid))").unwrap(), ["x: ann"]);
+    assert_eq!(query(&db, &scope, "(query (x) (age x a) (< 10 a) (<= a 40))").unwrap(), ["x: bob", "x: cid"]);
+    assert_eq!(query(&db, &scope, "(query (x) (age x a) (!= a 15))").unwrap(), ["x: ann", "x: cid"]);
+    // `=` binds either side, and compares numbers by value across their types
+    assert_eq!(query(&db, &scope, "(query (x) (= (+ 1 1) x))").unwrap(), ["x: 2"]);
+    assert_eq!(query(&db, &scope, "(query (x) (= x 1) (= 1.0 x))").unwrap(), ["x: 1"]);
+    assert_eq!(query(&db, &scope, "(query (x) (= x (list 1 y)) (= y 2))").unwrap(), ["x: '(1 2)"]);
+    assert_eq!(query(&db, &scope, "(query (x) (= x \"a\") (< x \"b\"))").unwrap(), ["x: \"a\""]);
+    // values a comparison does not apply to are an error, not a failed test
+    assert!(matches!(
+        query(&db, &scope, "(query (x) (age x a) (< a \"a\"))"),
+        Err(Error::TypeMismatch { .. })
+    ));
+    assert!(matches!(
+        query(&db, &scope, "(query (x) (age x _) (> x 1))"),
+        Err(Error::TypeMismatch { .. })
+    ));
+}
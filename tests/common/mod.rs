@@ -0,0 +1,56 @@
+//! Helpers shared by the integration tests, each test crate uses some of them.
+#![allow(dead_code)]
+
+use sexpr_ir::syntax::sexpr::one_unit_parse;
+
+use libakasha::engine::environment::{Database, Evaluation};
+use libakasha::engine::error::Error;
+use libakasha::engine::load::{query_iter_with, repl_eval, Row};
+use libakasha::structs::{scope::Scope, value::Handle};
+
+/// Loads the definitions of the program into the database, panics on a load error.
+pub fn load(db: Database, program: &[&str]) -> (Handle<Database>, Handle<Scope>) {
+    let db = Handle::new(db);
+    let scope = Handle::new(Scope::default());
+    for line in program {
+        eval(&db, &scope, line).unwrap();
+    }
+    (db, scope)
+}
+
+/// A database with default settings holding the program.
+pub fn program(program: &[&str]) -> (Handle<Database>, Handle<Scope>) {
+    load(Database::default(), program)
+}
+
+/// Evaluates one definition or query as the repl does.
+pub fn eval(db: &Handle<Database>, scope: &Handle<Scope>, input: &str) -> Result<Option<Vec<Row>>, Error> {
+    let input = one_unit_parse(input, "<test>").unwrap();
+    repl_eval(db, scope, &input)
+}
+
+fn show(row: &Row) -> String {
+    row.iter()
+        .map(|(k, v)| format!("{}: {}", k.0, v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The rows of the query as sorted text.
+pub fn query(db: &Handle<Database>, scope: &Handle<Scope>, query: &str) -> Result<Vec<String>, Error> {
+    let rows = eval(db, scope, query)?.expect("not a query");
+    let mut r: Vec<String> = rows.iter().map(show).collect();
+    r.sort();
+    Ok(r)
+}
+
+/// The rows of the query as sorted text, with the evaluation given, to compare them between evaluations.
+pub fn rows(db: &Handle<Database>, scope: &Handle<Scope>, query: &str, evaluation: Evaluation) -> Vec<String> {
+    let input = one_unit_parse(query, "<test>").unwrap();
+    let mut r: Vec<String> = query_iter_with(db, scope, &input, evaluation)
+        .unwrap()
+        .map(|row| show(&row.unwrap()))
+        .collect();
+    r.sort();
+    r
+}
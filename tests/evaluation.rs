@@ -0,0 +1,176 @@
+mod common;
+
+use sexpr_ir::gast::symbol::Symbol;
+
+use libakasha::engine::bottomup::materialized;
+use libakasha::engine::environment::{Database, Evaluation};
+use libakasha::structs::{scope::Scope, value::Handle};
+
+use common::{eval, load, program, rows};
+
+const JOINS: &[&str] = &[
+    "(fact parent 'ann 'bob)",
+    "(fact parent 'ann 'cid)",
+    "(fact parent 'bob 'dan)",
+    "(fact parent 'cid 'eve)",
+    "(fact parent 'dan 'fay)",
+    "(fact age 'ann 70)",
+    "(fact age 'bob 45)",
+    "(fact age 'cid 40)",
+    "(fact age 'dan 20)",
+    "(fact age 'eve 15)",
+    "(fact age 'fay 1)",
+    "(rule (grandparent x z) (parent x y) (parent y z))",
+    "(rule (adult x) (age x a) (>= a 18))",
+    "(rule (sibling x y) (parent p x) (parent p y) (!= x y))",
+    "(rule (childless x) (age x _) (not (parent x _)))",
+    "(fact num 1)",
+    "(fact num 2)",
+    "(fact pair (list 2) 'a)",
+    "(fact pair (vec 2 4) 'b)",
+];
+
+const JOIN_QUERIES: &[&str] = &[
+    "(query (x z) (parent x y) (parent y z))",
+    "(query (x y) (parent x y) (age x a) (age y b) (> a b))",
+    "(query (x z) (grandparent x z))",
+    "(query (x) (parent x y) (adult y))",
+    "(query (x y) (sibling x y))",
+    "(query (x) (childless x))",
+    "(query (x n) (age x _) (count n (parent x _)))",
+    "(query (x) (parent 'ann x) (parent x 'dan))",
+    "(query (x) (age x a) (age y (- a 25)))",
+    "(query (x) (num x) (pair (list (+ x 1)) _))",
+    "(query (x) (num x) (pair (vec x (* x 2)) _))",
+];
+
+#[test]
+fn hash_join_same_answers() {
+    let (plain, plain_scope) = load(Database::default(), JOINS);
+    let joined = Database {
+        hash_join: true,
+        ..Default::default()
+    };
+    let (joined, joined_scope) = load(joined, JOINS);
+    for query in JOIN_QUERIES {
+        for evaluation in [Evaluation::TopDown, Evaluation::BottomUp, Evaluation::Magic] {
+            let expected = rows(&plain, &plain_scope, query, evaluation);
+            assert!(!expected.is_empty(), "{}", query);
+            assert_eq!(expected, rows(&joined, &joined_scope, query, evaluation), "{}", query);
+        }
+    }
+}
+
+const CHAIN: &[&str] = &[
+    "(fact edge 1 2)",
+    "(fact edge 2 3)",
+    "(fact edge 3 4)",
+    "(fact edge 2 5)",
+    "(fact edge 5 4)",
+    "(fact edge 6 1)",
+];
+
+const CYCLE: &[&str] = &[
+    "(fact edge 1 2)",
+    "(fact edge 2 3)",
+    "(fact edge 3 1)",
+    "(fact edge 3 4)",
+    "(fact edge 5 5)",
+];
+
+const CLOSURE_QUERIES: &[&str] = &[
+    "(query (y) (path 1 y))",
+    "(query (x) (path x 4))",
+    "(query (x y) (path x y))",
+    "(query (x) (path x x))",
+    "(query (n) (count n (path 2 _)))",
+];
+
+fn closure(facts: &[&str], rules: &[&str], tabled: bool) -> (Handle<Database>, Handle<Scope>) {
+    let table: &[&str] = if tabled { &["(table path 2)"] } else { &[] };
+    let program: Vec<&str> = table.iter().chain(rules).chain(facts).copied().collect();
+    load(Database::default(), &program)
+}
+
+#[test]
+fn transitive_closure_evaluations() {
+    let right = [
+        "(rule (path x y) (edge x y))",
+        "(rule (path x z) (edge x y) (path y z))",
+    ];
+    let left = [
+        "(rule (path x y) (edge x y))",
+        "(rule (path x z) (path x y) (edge y z))",
+    ];
+    let (db, scope) = closure(CHAIN, &right, false);
+    assert_eq!(rows(&db, &scope, "(query (y) (path 1 y))", Evaluation::TopDown), ["y: 2", "y: 3", "y: 4", "y: 5"]);
+    // without tabling, top-down evaluation only ends on the acyclic graph with the right recursion
+    for query in CLOSURE_QUERIES {
+        let expected = rows(&db, &scope, query, Evaluation::TopDown);
+        assert_eq!(expected, rows(&db, &scope, query, Evaluation::BottomUp), "{}", query);
+        assert_eq!(expected, rows(&db, &scope, query, Evaluation::Magic), "{}", query);
+        for rules in [&right, &left] {
+            let (db, scope) = closure(CHAIN, rules, true);
+            assert_eq!(expected, rows(&db, &scope, query, Evaluation::TopDown), "{}", query);
+        }
+    }
+    for rules in [&right, &left] {
+        let (db, scope) = closure(CYCLE, rules, false);
+        let (tabled, tabled_scope) = closure(CYCLE, rules, true);
+        let expected = ["y: 1", "y: 2", "y: 3", "y: 4"];
+        assert_eq!(rows(&db, &scope, "(query (y) (path 1 y))", Evaluation::BottomUp), expected);
+        for query in CLOSURE_QUERIES {
+            let expected = rows(&db, &scope, query, Evaluation::BottomUp);
+            assert!(!expected.is_empty(), "{}", query);
+            assert_eq!(expected, rows(&db, &scope, query, Evaluation::Magic), "{}", query);
+            assert_eq!(expected, rows(&tabled, &tabled_scope, query, Evaluation::TopDown), "{}", query);
+        }
+    }
+}
+
+#[test]
+fn derived_tables_share_facts() {
+    let (db, scope) = program(&[
+        "(rule (path x y) (edge x y))",
+        "(rule (path x z) (edge x y) (path y z))",
+    ]);
+    for i in 0..100 {
+        eval(&db, &scope, &format!("(fact edge {} {})", i, i + 1)).unwrap();
+    }
+    let work = materialized(&db).unwrap();
+    let edge = (Handle::new(Symbol::new("edge")), 2);
+    assert!(Handle::ptr_eq(&db.facts.read().unwrap().0[&edge], &work.facts.read().unwrap().0[&edge]));
+    assert_eq!(work.facts.read().unwrap().0[&(Handle::new(Symbol::new("path")), 2)].len(), 5050);
+    // a shared table is copied when the facts change, the derived ones are computed again
+    eval(&db, &scope, "(fact edge 100 101)").unwrap();
+    assert_eq!(work.facts.read().unwrap().0[&edge].len(), 100);
+    assert_eq!(rows(&db, &scope, "(query (x) (path 99 x))", Evaluation::BottomUp), ["x: 100", "x: 101"]);
+    assert_eq!(rows(&db, &scope, "(query (x) (path 99 x))", Evaluation::Magic), ["x: 100", "x: 101"]);
+}
+
+#[test]
+fn planner_keeps_right_recursion() {
+    let rules = [
+        "(rule (path x y) (edge x y))",
+        "(rule (path x z) (edge x y) (path y z))",
+    ];
+    let planned = program(&rules);
+    let fixed = load(
+        Database {
+            fixed_goal_order: true,
+            ..Default::default()
+        },
+        &rules,
+    );
+    for (db, scope) in [&planned, &fixed] {
+        for i in 0..120 {
+            eval(db, scope, &format!("(fact edge {} {})", i, i + 1)).unwrap();
+        }
+    }
+    // a recursive call run before the goal binding its args would not end
+    for (query, n) in [("(query (x) (path x 115))", 115), ("(query (y) (path 110 y))", 10)] {
+        let expected = rows(&fixed.0, &fixed.1, query, Evaluation::TopDown);
+        assert_eq!(expected.len(), n, "{}", query);
+        assert_eq!(expected, rows(&planned.0, &planned.1, query, Evaluation::TopDown), "{}", query);
+    }
+}
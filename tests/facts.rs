@@ -0,0 +1,135 @@
+mod common;
+
+use sexpr_ir::gast::symbol::Symbol;
+use sexpr_ir::syntax::sexpr::one_unit_parse;
+
+use libakasha::engine::environment::Evaluation;
+use libakasha::engine::error::Error;
+use libakasha::engine::load::query_iter;
+use libakasha::structs::{
+    rule::Expr,
+    value::{Handle, Value},
+};
+
+use common::{eval, program, query, rows};
+
+#[test]
+fn indexes() {
+    let (db, scope) = program(&[
+        "(index edge 2 (1))",
+        "(fact edge 1 2)",
+        "(fact edge 2 3)",
+        "(fact edge 3 3)",
+        "(rule (path x y) (edge x y))",
+    ]);
+    // declared before the first fact, the index comes with it
+    let key = (Handle::new(Symbol::new("edge")), 2);
+    let cols: &[usize] = &[1];
+    assert!(db.facts.read().unwrap().0[&key].indexes.contains_key(cols));
+    assert_eq!(query(&db, &scope, "(query (x) (edge x 3))").unwrap(), ["x: 2", "x: 3"]);
+    assert_eq!(db.add_index("edge", 2, &[0, 1]), Ok(()));
+    assert_eq!(query(&db, &scope, "(query (x) (edge 1 x))").unwrap(), ["x: 2"]);
+
+    assert!(matches!(
+        eval(&db, &scope, "(index path 2 (0))"),
+        Err(Error::Unindexable { arity: 2, .. })
+    ));
+    db.register_relation("succ", 2, |_| Box::new(std::iter::empty()));
+    assert!(matches!(db.add_index("succ", 2, &[0]), Err(Error::Unindexable { arity: 2, .. })));
+    assert_eq!(
+        db.add_index("edge", 2, &[2]),
+        Err(Error::IndexOutOfRange {
+            index: Value::Uint(2),
+            length: 2
+        })
+    );
+    assert_eq!(eval(&db, &scope, "(index edge 2 x)"), Err(Error::InvalidForm));
+    // neither creates a table hiding the relation
+    assert!(!db.facts.read().unwrap().0.contains_key(&(Handle::new(Symbol::new("path")), 2)));
+    assert_eq!(query(&db, &scope, "(query (x) (path 1 x))").unwrap(), ["x: 2"]);
+}
+
+#[test]
+fn retract() {
+    let (db, scope) = program(&["(fact p 1 'a)", "(fact p 2 'a)", "(fact p 3 'b)", "(fact p 4 'b)"]);
+    assert_eq!(eval(&db, &scope, "(retract p 2 'a)"), Ok(None));
+    assert_eq!(query(&db, &scope, "(query (x) (p x _))").unwrap(), ["x: 1", "x: 3", "x: 4"]);
+    assert_eq!(db.retract("p", &[Expr::Variable(Handle::new(Symbol::new("x"))), Expr::Value(sym("b"))]), 2);
+    assert_eq!(query(&db, &scope, "(query (x) (p x _))").unwrap(), ["x: 1"]);
+    // the indexes lose the lines removed, a line added again is found once
+    assert_eq!(query(&db, &scope, "(query (x) (p 3 x))").unwrap(), Vec::<String>::new());
+    eval(&db, &scope, "(fact p 3 'b)").unwrap();
+    eval(&db, &scope, "(fact p 3 'b)").unwrap();
+    assert_eq!(query(&db, &scope, "(query (x) (p 3 x))").unwrap(), ["x: b"]);
+    assert_eq!(query(&db, &scope, "(query (x) (p x 'b))").unwrap(), ["x: 3"]);
+    assert_eq!(db.facts.read().unwrap().0[&(Handle::new(Symbol::new("p")), 2)].len(), 2);
+    assert_eq!(db.retract("p", &[Expr::Value(Value::Uint(9)), Expr::Value(sym("b"))]), 0);
+}
+
+#[test]
+fn retract_while_iterating() {
+    let (db, scope) = program(&[]);
+    for i in 0..10 {
+        eval(&db, &scope, &format!("(fact n {})", i)).unwrap();
+    }
+    // each fact is given once, even as the ones given before are removed
+    let input = one_unit_parse("(query (x) (n x))", "<test>").unwrap();
+    let mut seen = vec![];
+    for row in query_iter(&db, &scope, &input).unwrap() {
+        let x = row.unwrap()[0].1.clone();
+        assert_eq!(db.retract("n", &[Expr::Value(x.clone())]), 1);
+        seen.push(x);
+    }
+    seen.sort();
+    assert_eq!(seen, (0..10).map(Value::Uint).collect::<Vec<_>>());
+    assert!(db.facts.read().unwrap().0[&(Handle::new(Symbol::new("n")), 1)].is_empty());
+}
+
+fn sym(name: &str) -> Value {
+    Value::Sym(Handle::new(Symbol::new(name)))
+}
+
+#[test]
+fn bags() {
+    let (db, scope) = program(&[
+        "(bag sale 1)",
+        "(fact sale 10)",
+        "(fact sale 10)",
+        "(fact sale 20)",
+        "(bag owns 1)",
+        "(fact item 'a 1)",
+        "(fact item 'b 1)",
+        "(rule (owns x) (item _ x))",
+        "(rule (sold x) (sale x))",
+    ]);
+    let cases = [
+        ("(query (x) (sale x))", vec!["x: 10", "x: 10", "x: 20"]),
+        ("(query (x) (or (sale x)))", vec!["x: 10", "x: 10", "x: 20"]),
+        ("(query (x) (owns x))", vec!["x: 1", "x: 1"]),
+        ("(query (x) (= x 1) (owns x))", vec!["x: 1", "x: 1"]),
+        // a relation not declared a bag is a set, even of a bag
+        ("(query (x) (sold x))", vec!["x: 10", "x: 20"]),
+        ("(query (n) (count n (sale _)))", vec!["n: 3"]),
+    ];
+    for (input, expected) in cases {
+        for evaluation in [Evaluation::TopDown, Evaluation::BottomUp, Evaluation::Magic] {
+            assert_eq!(rows(&db, &scope, input, evaluation), expected, "{} {:?}", input, evaluation);
+        }
+    }
+}
+
+#[test]
+fn sets() {
+    let (db, scope) = program(&[
+        "(fact f 1)",
+        "(fact f 1)",
+        "(fact f 1.0)",
+        "(fact f (/ 0.0 0.0))",
+        "(fact f (/ 0.0 0.0))",
+        "(fact f 0.0)",
+        "(fact f -0.0)",
+    ]);
+    // all NaNs are the same value, as are -0.0 and 0.0
+    assert_eq!(db.facts.read().unwrap().0[&(Handle::new(Symbol::new("f")), 1)].len(), 4);
+    assert_eq!(query(&db, &scope, "(query (x) (f x))").unwrap().len(), 4);
+}
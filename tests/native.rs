@@ -0,0 +1,57 @@
+mod common;
+
+use libakasha::engine::error::Error;
+use libakasha::structs::value::Value;
+
+use common::{eval, program, query};
+
+#[test]
+fn native_functions() {
+    let (db, scope) = program(&[]);
+    db.register_function("double", Some(1), |args| match &args[0] {
+        Value::Uint(x) => Ok(Value::Uint(x * 2)),
+        _ => Err(Error::Custom("not a uint".into())),
+    });
+    db.register_function("concat", None, |args| Ok(Value::Uint(args.len() as u64)));
+    for line in [
+        "(define twenty (double 10))",
+        "(fact n (double 21))",
+        "(fact n twenty)",
+        "(rule (d x y) (n y) (= y (double x)))",
+    ] {
+        eval(&db, &scope, line).unwrap();
+    }
+    assert_eq!(query(&db, &scope, "(query (x) (n x))").unwrap(), ["x: 20", "x: 42"]);
+    assert_eq!(query(&db, &scope, "(query (y) (d 21 y))").unwrap(), ["y: 42"]);
+    // a native function shadows the built-in one of its name
+    assert_eq!(query(&db, &scope, "(query (x) (= x (concat \"a\" \"b\")))").unwrap(), ["x: 2"]);
+    assert_eq!(
+        eval(&db, &scope, "(fact n (double \"a\"))"),
+        Err(Error::Custom("not a uint".into()))
+    );
+    assert!(matches!(
+        query(&db, &scope, "(query (x) (= x (double 1 2)))"),
+        Err(Error::ArityMismatch { expected: 1, found: 2, .. })
+    ));
+}
+
+#[test]
+fn foreign_relations() {
+    let (db, scope) = program(&["(fact num 3)", "(fact num 7)"]);
+    db.register_relation("succ", 2, |args| match &args[0] {
+        Some(Value::Uint(x)) => Box::new(std::iter::once(vec![Value::Uint(*x), Value::Uint(x + 1)])),
+        _ => Box::new((0..3u64).map(|x| vec![Value::Uint(x), Value::Uint(x + 1)])),
+    });
+    // tuples not matching the bound args, or of another arity, are dropped
+    db.register_relation("noisy", 1, |_| {
+        Box::new(vec![vec![Value::Uint(1)], vec![], vec![Value::Uint(2)]].into_iter())
+    });
+    assert_eq!(query(&db, &scope, "(query (y) (succ 41 y))").unwrap(), ["y: 42"]);
+    assert_eq!(query(&db, &scope, "(query (x) (succ x 2))").unwrap(), ["x: 1"]);
+    assert_eq!(query(&db, &scope, "(query (x y) (num x) (succ x y))").unwrap(), ["x: 3, y: 4", "x: 7, y: 8"]);
+    assert_eq!(query(&db, &scope, "(query (x) (noisy x))").unwrap(), ["x: 1", "x: 2"]);
+    assert_eq!(query(&db, &scope, "(query (x) (noisy 2) (= x 0))").unwrap(), ["x: 0"]);
+    // rules of the same name and arity take precedence
+    eval(&db, &scope, "(rule (succ x y) (num x) (num y) (< x y))").unwrap();
+    assert_eq!(query(&db, &scope, "(query (x y) (succ x y))").unwrap(), ["x: 3, y: 7"]);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use sexpr_ir::syntax::sexpr::one_unit_parse;
+
+use libakasha::engine::environment::Database;
+use libakasha::engine::error::Error;
+use libakasha::engine::load::Loader;
+use libakasha::structs::{rule::RuleRecord, scope::Scope, value::Handle};
+
+use common::{eval, program, query};
+
+#[test]
+fn stratified_negation() {
+    let (db, scope) = program(&[
+        "(fact node 1)",
+        "(fact node 2)",
+        "(fact node 3)",
+        "(fact edge 1 2)",
+        "(rule (linked x) (edge x _))",
+        "(rule (linked x) (edge _ x))",
+        "(rule (lonely x) (node x) (not (linked x)))",
+    ]);
+    assert_eq!(query(&db, &scope, "(query (x) (lonely x))").unwrap(), ["x: 3"]);
+    assert_eq!(query(&db, &scope, "(query (x) (node x) (not (edge x _)))").unwrap(), ["x: 2", "x: 3"]);
+
+    // a relation depending on its own negation is rejected, the rules before stay
+    let r = eval(&db, &scope, "(rule (linked x) (node x) (not (lonely x)))");
+    assert!(matches!(r, Err(Error::Unstratifiable { arity: 1, .. })), "{:?}", r);
+    let r = eval(&db, &scope, "(rule (p x) (node x) (not (p x)))");
+    assert!(matches!(r, Err(Error::Unstratifiable { arity: 1, .. })), "{:?}", r);
+    assert!(db.rules.read().unwrap().0.keys().all(|k| k.0 .0.as_str() != "p"));
+    assert_eq!(query(&db, &scope, "(query (x) (lonely x))").unwrap(), ["x: 3"]);
+    assert_eq!(query(&db, &scope, "(query (x) (linked x))").unwrap(), ["x: 1", "x: 2"]);
+}
+
+#[test]
+fn stratified_rule_record() {
+    let db = Database::default();
+    let scope = Handle::new(Scope::default());
+    let mut rules = RuleRecord::default();
+    let mut load = |input: &str| {
+        let input = one_unit_parse(input, "<test>").unwrap();
+        rules.load(&db, &scope, &input)
+    };
+    assert_eq!(load("(rule (a x) (n x) (not (b x)))"), Some(Ok(())));
+    assert_eq!(load("(rule (b x) (n x))"), Some(Ok(())));
+    assert!(matches!(
+        load("(rule (b x) (n x) (count c (a _)) (= x c))"),
+        Some(Err(Error::Unstratifiable { .. }))
+    ));
+    assert_eq!(load("(fact n 1)"), None);
+    assert_eq!(rules.0.values().map(|x| x.0.len()).sum::<usize>(), 2);
+}
+
+#[test]
+fn aggregates() {
+    let (db, scope) = program(&[
+        "(fact sale 'ann 10)",
+        "(fact sale 'ann 20)",
+        "(fact sale 'bob 5)",
+        "(fact person 'ann)",
+        "(fact person 'bob)",
+        "(fact person 'cid)",
+        "(rule (total p s) (person p) (sum s x (sale p x)))",
+        "(rule (sales p n) (person p) (count n (sale p _)))",
+    ]);
+    assert_eq!(query(&db, &scope, "(query (n) (count n (sale _ _)))").unwrap(), ["n: 3"]);
+    // with the group bound before, a group without solutions still counts
+    assert_eq!(
+        query(&db, &scope, "(query (p s) (total p s))").unwrap(),
+        ["p: ann, s: 30", "p: bob, s: 5", "p: cid, s: 0"]
+    );
+    assert_eq!(
+        query(&db, &scope, "(query (p n) (sales p n))").unwrap(),
+        ["p: ann, n: 2", "p: bob, n: 1", "p: cid, n: 0"]
+    );
+    assert_eq!(query(&db, &scope, "(query (m) (max m x (sale _ x)))").unwrap(), ["m: 20"]);
+    assert_eq!(query(&db, &scope, "(query (m) (min m x (sale _ x)))").unwrap(), ["m: 5"]);
+    assert_eq!(query(&db, &scope, "(query (m) (avg m x (sale 'ann x)))").unwrap(), ["m: 15"]);
+    assert_eq!(query(&db, &scope, "(query (m) (sum m x (sale 'dan x)))").unwrap(), ["m: 0"]);
+
+    // an aggregate failing on its values is an error
+    eval(&db, &scope, "(fact sale 'dan \"a\")").unwrap();
+    assert!(matches!(
+        query(&db, &scope, "(query (m) (sum m x (sale _ x)))"),
+        Err(Error::TypeMismatch { .. })
+    ));
+    // a goal shaped as an aggregate, but without an inner goal, is a relation goal
+    eval(&db, &scope, "(fact sum 1 2 3)").unwrap();
+    assert_eq!(query(&db, &scope, "(query (x) (sum x 2 3))").unwrap(), ["x: 1"]);
+}
+
+#[test]
+fn aggregate_many_groups() {
+    let (db, scope) = program(&[]);
+    for i in 0..20000 {
+        eval(&db, &scope, &format!("(fact item {} {})", i % 5000, i)).unwrap();
+    }
+    let rows = query(&db, &scope, "(query (g n) (count n (item g _)))").unwrap();
+    assert_eq!(rows.len(), 5000);
+    assert!(rows.iter().all(|x| x.starts_with("g: ") && x.ends_with(", n: 4")));
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use libakasha::engine::error::Error;
+use libakasha::structs::value::{Handle, Value};
+
+use common::{eval, program, query};
+
+/// The value of an expression, as a query binds it.
+fn value(expr: &str) -> Value {
+    let (db, scope) = program(&[]);
+    let rows = eval(&db, &scope, &format!("(query (x) (= x {}))", expr)).expect(expr).unwrap();
+    rows[0][0].1.clone()
+}
+
+#[test]
+fn value_order() {
+    // in ascending order, each type after the ones before
+    let ordered = [
+        "(list)",
+        "false",
+        "true",
+        "-1.5",
+        "-1",
+        "0",
+        "0.5",
+        "(decimal \"0.75\")",
+        "1",
+        "1.0",
+        "(bigint \"100000000000000000000\")",
+        "(/ 0.0 0.0)",
+        "(char \"a\")",
+        "\"a\"",
+        "\"b\"",
+        "'a",
+        "'ab",
+        "'b",
+        "(list 1 2)",
+        "(list 1 3)",
+        "(vec 1)",
+        "(vec 1 2)",
+        "(dict (\"a\" . 1))",
+        "(dict (\"a\" . 2))",
+        "(bytes \"00\")",
+        "(bytes \"0000\")",
+        "(timestamp \"2021-05-01T00:00:00Z\")",
+        "(timestamp \"2021-05-02T00:00:00Z\")",
+        "(duration \"1s\")",
+        "(duration \"1m\")",
+    ];
+    let values: Vec<Value> = ordered.iter().map(|x| value(x)).collect();
+    for (i, a) in values.iter().enumerate() {
+        for (j, b) in values.iter().enumerate() {
+            assert_eq!(a.cmp(b), i.cmp(&j), "{} {}", ordered[i], ordered[j]);
+        }
+    }
+    let mut shuffled: Vec<Value> = values.iter().rev().cloned().collect();
+    shuffled.sort();
+    assert_eq!(shuffled, values);
+}
+
+#[test]
+fn dicts() {
+    let (db, scope) = program(&[
+        "(fact person (dict (\"name\" . \"bob\") (\"age\" . 30) (\"tags\" . (list 'a 'b))))",
+        "(fact person (dict (\"name\" . \"amy\") (\"age\" . 25)))",
+        "(fact person (dict (\"age\" . 25) (\"name\" . \"amy\")))",
+        "(rule (name-of (dict (\"name\" . n) . _) n))",
+    ]);
+    // the order of the entries does not matter
+    assert_eq!(query(&db, &scope, "(query (n) (count n (person _)))").unwrap(), ["n: 2"]);
+    assert_eq!(
+        query(&db, &scope, "(query (n a) (person (dict (\"name\" . n) (\"age\" . a))))").unwrap(),
+        ["n: \"amy\", a: 25"]
+    );
+    assert_eq!(
+        query(&db, &scope, "(query (n r) (person (dict (\"name\" . n) . r)))").unwrap(),
+        [
+            "n: \"amy\", r: (dict (\"age\" . 25))",
+            "n: \"bob\", r: (dict (\"age\" . 30) (\"tags\" . '(a b)))"
+        ]
+    );
+    assert_eq!(
+        query(&db, &scope, "(query (n) (person p) (name-of p n))").unwrap(),
+        ["n: \"amy\"", "n: \"bob\""]
+    );
+    // a rest matches the entries not given, on either side
+    let input = "(query (x y) (= (dict (\"a\" . x) . r) (dict (\"b\" . y) . s)) \
+                 (= r (dict (\"b\" . 2))) (= s (dict (\"a\" . 1))))";
+    assert_eq!(query(&db, &scope, input).unwrap(), ["x: 1, y: 2"]);
+    let input = "(query (x) (= (dict (\"a\" . x)) (dict (\"a\" . 1) (\"b\" . 2))))";
+    assert_eq!(query(&db, &scope, input).unwrap(), Vec::<String>::new());
+
+    assert_eq!(
+        query(&db, &scope, "(query (n) (= n (dict-get (dict (\"a\" . 1)) \"a\")))").unwrap(),
+        ["n: 1"]
+    );
+    assert_eq!(
+        query(&db, &scope, "(query (n) (= n (dict-keys (dict (\"b\" . 1) (\"a\" . 2)))))").unwrap(),
+        ["n: '(\"a\" \"b\")"]
+    );
+    assert_eq!(
+        query(&db, &scope, "(query (n) (= n (dict-insert (dict (\"a\" . 1)) \"a\" 2)))").unwrap(),
+        ["n: (dict (\"a\" . 2))"]
+    );
+    assert_eq!(
+        query(&db, &scope, "(query (n) (= n (dict-get (dict (\"a\" . 1)) \"b\")))"),
+        Err(Error::MissingKey(Handle::new("b".to_string())))
+    );
+    assert_eq!(eval(&db, &scope, "(fact bad (dict (\"a\" . 1) (\"a\" . 2)))"), Err(Error::InvalidForm));
+    assert_eq!(eval(&db, &scope, "(fact bad (dict (a . 1)))"), Err(Error::InvalidForm));
+}
+
+#[test]
+fn exact_numbers() {
+    let (db, scope) = program(&[
+        "(fact d (decimal \"10\"))",
+        "(fact d 10)",
+        "(fact d (bigint \"10\"))",
+        "(fact d (decimal \"10.00\"))",
+        "(fact d 1.0)",
+        "(fact d (decimal \"0.5\"))",
+        "(fact d (decimal \"0.50\"))",
+    ]);
+    // integers and decimals equal by value are one fact, a float is another
+    assert_eq!(query(&db, &scope, "(query (n) (count n (d _)))").unwrap(), ["n: 3"]);
+    assert_eq!(query(&db, &scope, "(query (x) (d 10) (= x 1))").unwrap(), ["x: 1"]);
+    assert_eq!(query(&db, &scope, "(query (x) (d (+ 4 6)) (= x 1))").unwrap(), ["x: 1"]);
+    assert_eq!(query(&db, &scope, "(query (x) (d (decimal \"0.500\")) (= x 1))").unwrap(), ["x: 1"]);
+    assert_eq!(query(&db, &scope, "(query (x) (d 1) (= x 1))").unwrap(), Vec::<String>::new());
+
+    let same = ["10", "(decimal \"10\")", "(decimal \"10.0\")", "(bigint \"10\")", "(- 20 10)"];
+    let values: std::collections::HashSet<Value> = same.iter().map(|x| value(x)).collect();
+    assert_eq!(values.len(), 1);
+    assert_eq!(value("(bigint \"-100000000000000000000\")"), value("(decimal \"-1e20\")"));
+    assert_ne!(value("1"), value("1.0"));
+}
+
+#[test]
+fn literals() {
+    let (db, scope) = program(&[
+        "(fact file 'a (bytes \"00ff\") (timestamp \"2021-05-01T00:00:00Z\") (duration \"90s\"))",
+        "(fact file 'b (bytes \"\") (timestamp \"2021-05-01T02:00:00+02:00\") (duration \"1h\"))",
+    ]);
+    assert_eq!(
+        query(&db, &scope, "(query (x b t d) (file x b t d))").unwrap(),
+        [
+            "x: a, b: (bytes \"00ff\"), t: (timestamp \"2021-05-01T00:00:00Z\"), d: (duration \"1m30s\")",
+            "x: b, b: (bytes \"\"), t: (timestamp \"2021-05-01T00:00:00Z\"), d: (duration \"1h\")"
+        ]
+    );
+    // the same instant in another offset is the same timestamp
+    assert_eq!(
+        query(&db, &scope, "(query (x) (file x _ (timestamp \"2021-05-01T00:00:00Z\") _))").unwrap(),
+        ["x: a", "x: b"]
+    );
+    assert_eq!(query(&db, &scope, "(query (x) (file x (bytes \"00FF\") _ (duration \"1m30s\")))").unwrap(), ["x: a"]);
+    // text not of the type is an error
+    for input in ["(fact file 'c (bytes \"0\"))", "(fact file 'c (timestamp \"yesterday\"))"] {
+        let r = eval(&db, &scope, input);
+        assert!(matches!(r, Err(Error::TypeMismatch { .. })), "{} {:?}", input, r);
+    }
+}
+
+#[test]
+fn display_round_trip() {
+    let exprs = [
+        "(list 1 \"a\" 'b)",
+        "(list 1 (list 2 'c) (list))",
+        "'(1 . 2)",
+        "(list (timestamp \"2021-05-01T00:00:00Z\") 'a)",
+        "(list (bigint \"100000000000000000000\") (decimal \"1.5\") (bytes \"00ff\") (duration \"1s\"))",
+        "(list (char \"a\") (list 'b (decimal \"0.5\")))",
+        "(list 'a (vec 'b 1) (dict (\"k\" . 'c)))",
+        "(list 1 . (vec 'a))",
+        "(vec 'a (list 'b) (bigint \"-100000000000000000000\"))",
+        "(dict (\"a\" . 'b) (\"c\" . (list (bytes \"01\"))))",
+    ];
+    for expr in exprs {
+        let v = value(expr);
+        assert_eq!(value(&v.to_string()), v, "{} {}", expr, v);
+    }
+    assert_eq!(value("(list 1 2 'a)").to_string(), "'(1 2 a)");
+    assert_eq!(value("(list 1 (list 2))").to_string(), "'(1 (2))");
+    assert_eq!(value("(list 'a (bytes \"01\"))").to_string(), "(list 'a (bytes \"01\"))");
+}